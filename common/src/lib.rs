@@ -332,6 +332,20 @@ pub enum Action {
     SetValue,
 
     ShowContextMenu,
+
+    /// Make the target node the only selected item in its selection
+    /// container, e.g. a list box or tab list.
+    Select,
+    /// Select the target node without deselecting the other selected items
+    /// in its selection container. Only applies to multiselectable
+    /// containers.
+    AddToSelection,
+    /// Deselect the target node, keeping the other selected items
+    /// in its selection container selected.
+    RemoveFromSelection,
+    /// Select every item in the target node, which must be a multiselectable
+    /// selection container.
+    SelectAll,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -651,6 +665,39 @@ pub struct TextSelection {
     pub focus: TextPosition,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[cfg_attr(feature = "serde", serde(crate = "serde"))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub enum TextAnnotationKind {
+    SpellingError,
+    GrammarError,
+    SearchMatch,
+    Suggestion,
+}
+
+/// A span of text that's marked as, for example, a spelling error,
+/// without splitting the inline text boxes at the span's boundaries
+/// as the per-node flags like [`Node::is_spelling_error`] require.
+/// The order of the two endpoints doesn't matter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[cfg_attr(feature = "serde", serde(crate = "serde"))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct TextAnnotation {
+    pub kind: TextAnnotationKind,
+    pub anchor: TextPosition,
+    pub focus: TextPosition,
+    /// A node with more information about the annotation, such as
+    /// a suggested correction.
+    #[cfg_attr(feature = "serde", serde(default))]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub details: Option<NodeId>,
+}
+
 /// A single accessible object. A complete UI is represented as a tree of these.
 #[derive(Clone, Debug, Default, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -1087,6 +1134,10 @@ pub struct Node {
 
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub text_selection: Option<TextSelection>,
+    /// Annotations on the text of this node's inline text boxes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "is_empty"))]
+    pub text_annotations: Vec<TextAnnotation>,
 
     #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub aria_column_count: Option<usize>,
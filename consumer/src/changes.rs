@@ -0,0 +1,288 @@
+// Copyright 2023 The AccessKit Authors. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (found in
+// the LICENSE-APACHE file) or the MIT license (found in
+// the LICENSE-MIT file), at your option.
+
+use accesskit::{NodeId, TextAnnotation, Tree as TreeData};
+use futures_core::Stream;
+use std::{
+    pin::Pin,
+    sync::{mpsc, Arc},
+    task::{Context, Poll},
+};
+
+use crate::{
+    node::{DetachedNode, Node},
+    text::Delta as TextDelta,
+    tree::{ChangeHandler, State as TreeState},
+};
+
+/// A change made by a tree update, as delivered to subscribers.
+///
+/// Unlike the arguments to [`ChangeHandler`] methods, a change doesn't
+/// borrow the tree, so nodes in the new state are identified by ID.
+/// Look them up in [`Event::state`].
+#[derive(Clone)]
+pub enum Change {
+    NodeAdded(NodeId),
+    NodeUpdated {
+        old_node: DetachedNode,
+        id: NodeId,
+    },
+    NodeMoved {
+        id: NodeId,
+        old_parent: DetachedNode,
+        new_parent_id: NodeId,
+    },
+    ChildrenReordered(NodeId),
+    RootChanged {
+        old_root: DetachedNode,
+        new_root_id: NodeId,
+    },
+    TreeDataChanged {
+        old_tree: TreeData,
+        new_tree: TreeData,
+    },
+    FocusMoved {
+        old_node: Option<DetachedNode>,
+        new_node_id: Option<NodeId>,
+    },
+    NodeRemoved(DetachedNode),
+    /// The set of selected items in a selection container changed.
+    SelectionChanged(NodeId),
+    TextAnnotationAdded {
+        id: NodeId,
+        annotation: TextAnnotation,
+    },
+    TextAnnotationRemoved {
+        id: NodeId,
+        annotation: TextAnnotation,
+    },
+    TextChanged {
+        id: NodeId,
+        delta: TextDelta,
+    },
+}
+
+/// A change, along with the state of the tree right after the update
+/// that made it.
+#[derive(Clone)]
+pub struct Event {
+    pub state: Arc<TreeState>,
+    pub change: Change,
+}
+
+/// The events sent to a subscriber created with
+/// [`Tree::subscribe_async`](crate::Tree::subscribe_async).
+/// The stream ends once the tree has been dropped and all pending
+/// events have been received. Dropping the stream unsubscribes.
+pub struct EventStream(pub(crate) async_channel::Receiver<Event>);
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+pub(crate) enum Subscriber {
+    Blocking(mpsc::Sender<Event>),
+    Async(async_channel::Sender<Event>),
+}
+
+impl Subscriber {
+    /// Returns false if the receiver has been dropped.
+    fn send(&self, event: Event) -> bool {
+        match self {
+            Self::Blocking(sender) => sender.send(event).is_ok(),
+            // The channel is unbounded, so this only fails if it's closed.
+            Self::Async(sender) => sender.try_send(event).is_ok(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Recorder(Vec<Change>);
+
+impl ChangeHandler for Recorder {
+    fn node_added(&mut self, node: &Node) {
+        self.0.push(Change::NodeAdded(node.id()));
+    }
+    fn node_updated(&mut self, old_node: &DetachedNode, new_node: &Node) {
+        self.0.push(Change::NodeUpdated {
+            old_node: old_node.clone(),
+            id: new_node.id(),
+        });
+    }
+    fn node_moved(&mut self, node: &Node, old_parent: &DetachedNode, new_parent: &Node) {
+        self.0.push(Change::NodeMoved {
+            id: node.id(),
+            old_parent: old_parent.clone(),
+            new_parent_id: new_parent.id(),
+        });
+    }
+    fn children_reordered(&mut self, parent: &Node) {
+        self.0.push(Change::ChildrenReordered(parent.id()));
+    }
+    fn root_changed(&mut self, old_root: &DetachedNode, new_root: &Node) {
+        self.0.push(Change::RootChanged {
+            old_root: old_root.clone(),
+            new_root_id: new_root.id(),
+        });
+    }
+    fn tree_data_changed(&mut self, old_tree: &TreeData, new_tree: &TreeData) {
+        self.0.push(Change::TreeDataChanged {
+            old_tree: old_tree.clone(),
+            new_tree: new_tree.clone(),
+        });
+    }
+    fn focus_moved(&mut self, old_node: Option<&DetachedNode>, new_node: Option<&Node>) {
+        self.0.push(Change::FocusMoved {
+            old_node: old_node.cloned(),
+            new_node_id: new_node.map(|node| node.id()),
+        });
+    }
+    fn node_removed(&mut self, node: &DetachedNode, _current_state: &TreeState) {
+        self.0.push(Change::NodeRemoved(node.clone()));
+    }
+    fn selection_changed(&mut self, container: &Node) {
+        self.0.push(Change::SelectionChanged(container.id()));
+    }
+    fn text_annotation_added(&mut self, node: &Node, annotation: &TextAnnotation) {
+        self.0.push(Change::TextAnnotationAdded {
+            id: node.id(),
+            annotation: annotation.clone(),
+        });
+    }
+    fn text_annotation_removed(&mut self, node: &Node, annotation: &TextAnnotation) {
+        self.0.push(Change::TextAnnotationRemoved {
+            id: node.id(),
+            annotation: annotation.clone(),
+        });
+    }
+    fn text_changed(&mut self, document: &Node, delta: &TextDelta) {
+        self.0.push(Change::TextChanged {
+            id: document.id(),
+            delta: delta.clone(),
+        });
+    }
+}
+
+impl Recorder {
+    /// Sends the recorded changes to every subscriber, forgetting
+    /// the subscribers whose receivers have been dropped.
+    pub(crate) fn send(self, subscribers: &mut Vec<Subscriber>, state: &Arc<TreeState>) {
+        subscribers.retain(|subscriber| {
+            self.0.iter().all(|change| {
+                subscriber.send(Event {
+                    state: Arc::clone(state),
+                    change: change.clone(),
+                })
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use accesskit::{Node, NodeId, Role, TreeUpdate};
+    use futures_lite::{future::block_on, StreamExt};
+    use std::{num::NonZeroU128, sync::Arc};
+
+    use super::Change;
+    use crate::tests::NullActionHandler;
+
+    const NODE_ID_2: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(2) });
+
+    fn add_child(tree: &crate::Tree) {
+        let root_id = tree.read().root().id();
+        tree.update(TreeUpdate {
+            nodes: vec![
+                (
+                    root_id,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![NODE_ID_2],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_2,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: None,
+            focus: None,
+        });
+    }
+
+    fn empty_tree() -> crate::Tree {
+        let root_id = NodeId(NonZeroU128::new(1).unwrap());
+        let update = TreeUpdate {
+            nodes: vec![(
+                root_id,
+                Arc::new(Node {
+                    role: Role::Window,
+                    ..Default::default()
+                }),
+            )],
+            tree: Some(accesskit::Tree::new(root_id)),
+            focus: None,
+        };
+        crate::Tree::new(update, Box::new(NullActionHandler {}))
+    }
+
+    #[test]
+    fn every_subscriber_receives_changes() {
+        let tree = empty_tree();
+        let first = tree.subscribe();
+        let second = tree.subscribe();
+        add_child(&tree);
+        for receiver in [first, second] {
+            let events = receiver.try_iter().collect::<Vec<_>>();
+            assert!(events
+                .iter()
+                .any(|event| matches!(event.change, Change::NodeAdded(id) if id == NODE_ID_2)));
+            assert!(events
+                .iter()
+                .any(|event| matches!(&event.change, Change::NodeUpdated { id, .. } if *id == tree.read().root().id())));
+            for event in &events {
+                assert!(event.state.has_node(NODE_ID_2));
+            }
+        }
+    }
+
+    #[test]
+    fn async_subscriber_is_a_stream() {
+        let tree = empty_tree();
+        let mut receiver = tree.subscribe_async();
+        add_child(&tree);
+        // Dropping the tree closes the stream once the pending events
+        // have been received.
+        drop(tree);
+        let mut added = false;
+        while let Some(event) = block_on(receiver.next()) {
+            added |= matches!(event.change, Change::NodeAdded(id) if id == NODE_ID_2);
+        }
+        assert!(added);
+    }
+
+    #[test]
+    fn dropped_receiver_unsubscribes() {
+        let tree = empty_tree();
+        let receiver = tree.subscribe();
+        let kept = tree.subscribe();
+        drop(receiver);
+        add_child(&tree);
+        assert_eq!(tree.subscribers.lock().len(), 1);
+        assert!(kept.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,428 @@
+// Copyright 2023 The AccessKit Authors. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (found in
+// the LICENSE-APACHE file) or the MIT license (found in
+// the LICENSE-MIT file), at your option.
+
+use accesskit::Role;
+
+use crate::{
+    iterators::FilterResult,
+    node::{DetachedNode, Node, NodeState},
+};
+
+/// The roles of nodes that are left out of the platform trees by default,
+/// while their children are exposed in their place.
+pub const DEFAULT_EXCLUDED_ROLES: &[Role] = &[
+    Role::Presentation,
+    Role::GenericContainer,
+    Role::InlineTextBox,
+];
+
+/// Decides which nodes are exposed to assistive technologies, so that
+/// every platform adapter exposes the same tree. A policy is built with
+/// [`FilterPolicyBuilder`], and can be stored in a constant.
+///
+/// The rules are applied in this order:
+///
+/// 1. The focused node is always included.
+/// 2. If [`FilterPolicyBuilder::include_root`] was set, so is the root.
+/// 3. Hidden nodes, and nodes with a hidden ancestor, are excluded along
+///    with their descendants.
+/// 4. If [`FilterPolicyBuilder::hide_outside_modal`] was set, nodes that are
+///    [hidden by the active modal](Node::is_hidden_by_modal) are excluded
+///    along with their descendants.
+/// 5. Unless turned off with [`FilterPolicyBuilder::presentational_children`],
+///    nodes that have an ancestor with
+///    [presentational children](NodeState::has_presentational_children)
+///    are excluded along with their descendants.
+/// 6. Nodes with one of the excluded roles, or for which the platform's
+///    [extra check](FilterPolicyBuilder::exclude_node_if) returns true,
+///    are excluded, but their children may still be included.
+#[derive(Clone, Copy)]
+pub struct FilterPolicy {
+    excluded_roles: &'static [Role],
+    include_root: bool,
+    presentational_children: bool,
+    hide_outside_modal: bool,
+    exclude_node_if: Option<fn(&NodeState) -> bool>,
+}
+
+impl FilterPolicy {
+    pub fn filter(&self, node: &Node) -> FilterResult {
+        self.filter_common(
+            node.state(),
+            node.is_focused(),
+            node.is_root(),
+            node.is_hidden(),
+            || node.is_hidden_by_modal(),
+            || node.has_presentational_ancestor(),
+        )
+    }
+
+    pub fn filter_detached(&self, node: &DetachedNode) -> FilterResult {
+        self.filter_common(
+            node.state(),
+            node.is_focused(),
+            node.is_root(),
+            node.is_hidden(),
+            || node.is_hidden_by_modal(),
+            || node.has_presentational_ancestor(),
+        )
+    }
+
+    fn filter_common(
+        &self,
+        state: &NodeState,
+        is_focused: bool,
+        is_root: bool,
+        is_hidden: bool,
+        is_hidden_by_modal: impl FnOnce() -> bool,
+        has_presentational_ancestor: impl FnOnce() -> bool,
+    ) -> FilterResult {
+        if is_focused || (is_root && self.include_root) {
+            return FilterResult::Include;
+        }
+
+        if is_hidden || (self.hide_outside_modal && is_hidden_by_modal()) {
+            return FilterResult::ExcludeSubtree;
+        }
+
+        if self.presentational_children && has_presentational_ancestor() {
+            return FilterResult::ExcludeSubtree;
+        }
+
+        if self.excluded_roles.contains(&state.role())
+            || matches!(self.exclude_node_if, Some(exclude) if exclude(state))
+        {
+            return FilterResult::ExcludeNode;
+        }
+
+        FilterResult::Include
+    }
+}
+
+impl Default for FilterPolicy {
+    fn default() -> Self {
+        FilterPolicyBuilder::new().build()
+    }
+}
+
+/// Builds a [`FilterPolicy`] with platform-specific tweaks.
+///
+/// The adapters share the default policy, and only deviate from it where
+/// their platform requires:
+///
+/// - The Windows adapter also uses a policy with
+///   [`include_root`](Self::include_root) for navigating from the root,
+///   since UI Automation always exposes the root of the fragment.
+/// - The Unix adapter sets [`hide_outside_modal`](Self::hide_outside_modal),
+///   since it's the only one that also reports the modal state.
+/// - The macOS adapter adds an [extra check](Self::exclude_node_if) for
+///   nodes that it has no role for.
+#[derive(Clone, Copy)]
+pub struct FilterPolicyBuilder {
+    policy: FilterPolicy,
+}
+
+impl FilterPolicyBuilder {
+    pub const fn new() -> Self {
+        Self {
+            policy: FilterPolicy {
+                excluded_roles: DEFAULT_EXCLUDED_ROLES,
+                include_root: false,
+                presentational_children: true,
+                hide_outside_modal: false,
+                exclude_node_if: None,
+            },
+        }
+    }
+
+    /// Replaces [`DEFAULT_EXCLUDED_ROLES`].
+    pub const fn excluded_roles(mut self, roles: &'static [Role]) -> Self {
+        self.policy.excluded_roles = roles;
+        self
+    }
+
+    /// Sets whether the root is included even if it would otherwise be
+    /// excluded. This is off by default, since only the Windows adapter
+    /// must expose its root.
+    pub const fn include_root(mut self, include: bool) -> Self {
+        self.policy.include_root = include;
+        self
+    }
+
+    /// Sets whether the descendants of nodes with presentational children
+    /// are excluded, as ARIA requires. This is on by default.
+    pub const fn presentational_children(mut self, exclude: bool) -> Self {
+        self.policy.presentational_children = exclude;
+        self
+    }
+
+    /// Sets whether nodes outside the [active modal](crate::TreeState::modal)
+    /// are excluded, as browsers do for `aria-modal`. This is off by default,
+    /// since assistive technologies also need the modal state to make sense
+    /// of the pruned tree, and not every adapter reports it yet.
+    pub const fn hide_outside_modal(mut self, hide: bool) -> Self {
+        self.policy.hide_outside_modal = hide;
+        self
+    }
+
+    /// Also excludes the nodes for which `exclude` returns true, for example
+    /// because the platform has no role to expose them with. Like the
+    /// excluded roles, this is only checked once the node is known not to
+    /// be hidden, so a hidden node still hides its descendants.
+    pub const fn exclude_node_if(mut self, exclude: fn(&NodeState) -> bool) -> Self {
+        self.policy.exclude_node_if = Some(exclude);
+        self
+    }
+
+    pub const fn build(self) -> FilterPolicy {
+        self.policy
+    }
+}
+
+impl Default for FilterPolicyBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use accesskit::{Node, NodeId, Role, Tree, TreeUpdate};
+    use std::{num::NonZeroU128, sync::Arc};
+
+    use super::{FilterPolicy, FilterPolicyBuilder};
+    use crate::{tests::*, FilterResult, NodeState};
+
+    const WINDOW_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(1) });
+    const BUTTON_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(2) });
+    const CONTAINER_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(3) });
+    const LABEL_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(4) });
+    const OTHER_BUTTON_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(5) });
+
+    fn button_tree(button_role: Role, focus: Option<NodeId>) -> crate::Tree {
+        let update = TreeUpdate {
+            nodes: vec![
+                (
+                    WINDOW_ID,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![BUTTON_ID],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    BUTTON_ID,
+                    Arc::new(Node {
+                        role: button_role,
+                        children: vec![CONTAINER_ID],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    CONTAINER_ID,
+                    Arc::new(Node {
+                        role: Role::GenericContainer,
+                        children: vec![LABEL_ID],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    LABEL_ID,
+                    Arc::new(Node {
+                        role: Role::StaticText,
+                        name: Some("OK".into()),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: Some(Tree::new(WINDOW_ID)),
+            focus,
+        };
+        crate::Tree::new(update, Box::new(NullActionHandler {}))
+    }
+
+    fn results(tree: &crate::Tree, policy: &FilterPolicy) -> Vec<FilterResult> {
+        let state = tree.read();
+        [WINDOW_ID, BUTTON_ID, CONTAINER_ID, LABEL_ID]
+            .iter()
+            .map(|id| {
+                let node = state.node_by_id(*id).unwrap();
+                let result = policy.filter(&node);
+                assert_eq!(policy.filter_detached(&node.detached()), result);
+                result
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_policy() {
+        let tree = test_tree();
+        let policy = FilterPolicy::default();
+        let state = tree.read();
+        let filter = |id| policy.filter(&state.node_by_id(id).unwrap());
+        assert_eq!(FilterResult::Include, filter(ROOT_ID));
+        assert_eq!(FilterResult::Include, filter(PARAGRAPH_0_ID));
+        assert_eq!(FilterResult::Include, filter(PARAGRAPH_1_IGNORED_ID));
+        assert_eq!(
+            FilterResult::ExcludeNode,
+            filter(EMPTY_CONTAINER_3_0_IGNORED_ID)
+        );
+        assert_eq!(FilterResult::Include, filter(STATIC_TEXT_3_1_0_ID));
+        assert_eq!(FilterResult::Include, filter(BUTTON_3_2_ID));
+    }
+
+    #[test]
+    fn hidden_subtree() {
+        let tree = button_tree(Role::Group, None);
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                CONTAINER_ID,
+                Arc::new(Node {
+                    role: Role::GenericContainer,
+                    children: vec![LABEL_ID],
+                    hidden: true,
+                    ..Default::default()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        assert_eq!(
+            results(&tree, &FilterPolicy::default()),
+            [
+                FilterResult::Include,
+                FilterResult::Include,
+                FilterResult::ExcludeSubtree,
+                FilterResult::ExcludeSubtree
+            ]
+        );
+    }
+
+    #[test]
+    fn presentational_children() {
+        let tree = button_tree(Role::Button, None);
+        assert_eq!(
+            results(&tree, &FilterPolicy::default()),
+            [
+                FilterResult::Include,
+                FilterResult::Include,
+                FilterResult::ExcludeSubtree,
+                FilterResult::ExcludeSubtree
+            ]
+        );
+        let policy = FilterPolicyBuilder::new()
+            .presentational_children(false)
+            .build();
+        assert_eq!(
+            results(&tree, &policy),
+            [
+                FilterResult::Include,
+                FilterResult::Include,
+                FilterResult::ExcludeNode,
+                FilterResult::Include
+            ]
+        );
+    }
+
+    #[test]
+    fn presentational_children_follow_role_changes() {
+        let tree = button_tree(Role::Button, None);
+        let policy = FilterPolicy::default();
+        assert_eq!(results(&tree, &policy)[3], FilterResult::ExcludeSubtree);
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                BUTTON_ID,
+                Arc::new(Node {
+                    role: Role::Group,
+                    children: vec![CONTAINER_ID],
+                    ..Default::default()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        assert_eq!(results(&tree, &policy)[3], FilterResult::Include);
+    }
+
+    #[test]
+    fn focused_node_is_always_included() {
+        let tree = button_tree(Role::Button, Some(CONTAINER_ID));
+        assert_eq!(
+            results(&tree, &FilterPolicy::default())[2],
+            FilterResult::Include
+        );
+    }
+
+    #[test]
+    fn hide_outside_modal() {
+        let tree = button_tree(Role::Group, None);
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                CONTAINER_ID,
+                Arc::new(Node {
+                    role: Role::Dialog,
+                    children: vec![LABEL_ID],
+                    modal: true,
+                    ..Default::default()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        tree.update(TreeUpdate {
+            nodes: vec![
+                (
+                    WINDOW_ID,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![BUTTON_ID, OTHER_BUTTON_ID],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    OTHER_BUTTON_ID,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: None,
+            focus: None,
+        });
+        let policy = FilterPolicyBuilder::new().hide_outside_modal(true).build();
+        assert_eq!(results(&tree, &policy), [FilterResult::Include; 4]);
+        let state = tree.read();
+        let outside = state.node_by_id(OTHER_BUTTON_ID).unwrap();
+        assert_eq!(policy.filter(&outside), FilterResult::ExcludeSubtree);
+        assert_eq!(
+            FilterPolicy::default().filter(&outside),
+            FilterResult::Include
+        );
+    }
+
+    #[test]
+    fn platform_tweaks() {
+        const POLICY: FilterPolicy = FilterPolicyBuilder::new()
+            .excluded_roles(&[Role::Window])
+            .include_root(true)
+            .exclude_node_if(is_button)
+            .presentational_children(false)
+            .build();
+        fn is_button(state: &NodeState) -> bool {
+            state.role() == Role::Button
+        }
+        let tree = button_tree(Role::Button, None);
+        assert_eq!(
+            results(&tree, &POLICY),
+            [
+                FilterResult::Include,
+                FilterResult::ExcludeNode,
+                FilterResult::Include,
+                FilterResult::Include
+            ]
+        );
+    }
+}
@@ -6,22 +6,31 @@
 pub(crate) mod tree;
 pub use tree::{ChangeHandler as TreeChangeHandler, State as TreeState, Tree};
 
+pub(crate) mod changes;
+pub use changes::{Change as TreeChange, Event as TreeEvent, EventStream as TreeEventStream};
+
 pub(crate) mod node;
 pub use node::{DetachedNode, Node, NodeState};
 
 pub(crate) mod iterators;
 pub use iterators::FilterResult;
 
+pub(crate) mod filters;
+pub use filters::{FilterPolicy, FilterPolicyBuilder, DEFAULT_EXCLUDED_ROLES};
+
+pub(crate) mod spatial;
+
 pub(crate) mod text;
 pub use text::{
-    AttributeValue as TextAttributeValue, Position as TextPosition, Range as TextRange,
-    WeakRange as WeakTextRange,
+    Annotation as TextAnnotation, AttributeValue as TextAttributeValue, Delta as TextDelta,
+    Format as TextFormat, LiveRange as LiveTextRange, Position as TextPosition, Range as TextRange,
+    Unit as TextUnit, WeakRange as WeakTextRange,
 };
 
 #[cfg(test)]
 mod tests {
     use accesskit::kurbo::{Affine, Rect, Vec2};
-    use accesskit::{ActionHandler, ActionRequest, Node, NodeId, Role, Tree, TreeUpdate};
+    use accesskit::{ActionHandler, ActionRequest, Live, Node, NodeId, Role, Tree, TreeUpdate};
     use std::num::NonZeroU128;
     use std::sync::Arc;
 
@@ -173,4 +182,57 @@ mod tests {
             FilterResult::Include
         }
     }
+
+    pub fn deep_tree_node_id(n: u128) -> NodeId {
+        NodeId(NonZeroU128::new(n).unwrap())
+    }
+
+    /// Returns a tree whose root is a polite live region with a single chain
+    /// of `depth` nested nodes below it. Every node in the chain but the last
+    /// is a container that's offset by one unit. The last is a button
+    /// with bounds, and its ID is `depth + 1`.
+    pub fn deep_tree(depth: u128) -> crate::tree::Tree {
+        let mut nodes = vec![(
+            deep_tree_node_id(1),
+            Arc::new(Node {
+                role: Role::Window,
+                live: Some(Live::Polite),
+                children: vec![deep_tree_node_id(2)],
+                ..Default::default()
+            }),
+        )];
+        for n in 2..=depth {
+            nodes.push((
+                deep_tree_node_id(n),
+                Arc::new(Node {
+                    role: Role::GenericContainer,
+                    transform: Some(Box::new(Affine::translate(Vec2::new(1.0, 0.0)))),
+                    children: vec![deep_tree_node_id(n + 1)],
+                    ..Default::default()
+                }),
+            ));
+        }
+        nodes.push((
+            deep_tree_node_id(depth + 1),
+            Arc::new(Node {
+                role: Role::Button,
+                bounds: Some(Rect::new(0.0, 0.0, 10.0, 10.0)),
+                ..Default::default()
+            }),
+        ));
+        let initial_update = TreeUpdate {
+            nodes,
+            tree: Some(Tree::new(deep_tree_node_id(1))),
+            focus: None,
+        };
+        crate::tree::Tree::new(initial_update, Box::new(NullActionHandler {}))
+    }
+
+    pub fn deep_tree_filter(node: &crate::Node) -> FilterResult {
+        if node.role() == Role::GenericContainer {
+            FilterResult::ExcludeNode
+        } else {
+            FilterResult::Include
+        }
+    }
 }
@@ -12,23 +12,139 @@ use std::{iter::FusedIterator, ops::Deref, sync::Arc};
 
 use accesskit::kurbo::{Affine, Point, Rect};
 use accesskit::{
-    Action, CheckedState, DefaultActionVerb, Live, Node as NodeData, NodeId, Role, TextSelection,
+    Action, CheckedState, DefaultActionVerb, Live, Node as NodeData, NodeId, Role, TextDecoration,
+    TextDirection, TextSelection, VerticalOffset,
 };
+use once_cell::sync::OnceCell;
 
 use crate::iterators::{
     FilterResult, FilteredChildren, FollowingFilteredSiblings, FollowingSiblings, LabelledBy,
     PrecedingFilteredSiblings, PrecedingSiblings,
 };
+use crate::text::SynthesizedText;
 use crate::tree::State as TreeState;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub(crate) struct ParentAndIndex(pub(crate) NodeId, pub(crate) usize);
 
+/// Values that depend on other nodes as well as the node itself, computed
+/// when first needed. The tree replaces a node's cache whenever an update
+/// may change one of these values, so a cache can be shared by every version
+/// of the tree state in which the node has the same values.
+#[derive(Default)]
+pub(crate) struct NodeCache {
+    name: OnceCell<Option<String>>,
+    live: OnceCell<Live>,
+    transform: OnceCell<Affine>,
+    presentational_ancestor: OnceCell<bool>,
+    modal_scope: OnceCell<ModalScope>,
+    /// The position and size of each child's ordered set, by child index.
+    sibling_set_positions: OnceCell<Vec<Option<(usize, usize)>>>,
+    inherited: OnceCell<Arc<InheritedState>>,
+    pub(crate) synthesized_text: OnceCell<Option<SynthesizedText>>,
+}
+
+/// Where a node is relative to the [active modal](TreeState::modal).
+#[derive(Clone, Copy, PartialEq)]
+enum ModalScope {
+    /// The node is the modal or one of its descendants.
+    Inside,
+    /// The node is one of the modal's ancestors.
+    Ancestor,
+    /// The node is hidden by the modal.
+    Outside,
+}
+
+/// The properties that a node's descendants share unless they set
+/// their own, combined from the node and its ancestors.
+#[derive(Default, PartialEq)]
+pub(crate) struct InheritedState {
+    disabled: bool,
+    hidden: bool,
+    /// Whether the nearest enclosing grid or tree grid is read-only,
+    /// which ARIA propagates to its cells.
+    grid_read_only: bool,
+    language: Option<Box<str>>,
+    font_family: Option<Box<str>>,
+    font_size: Option<f32>,
+    font_weight: Option<f32>,
+    background_color: Option<u32>,
+    foreground_color: Option<u32>,
+    text_direction: Option<TextDirection>,
+    bold: bool,
+    italic: bool,
+    overline: Option<TextDecoration>,
+    strikethrough: Option<TextDecoration>,
+    underline: Option<TextDecoration>,
+    vertical_offset: Option<VerticalOffset>,
+}
+
+impl InheritedState {
+    fn new(parent: &InheritedState, data: &NodeData) -> Self {
+        Self {
+            disabled: parent.disabled || data.disabled,
+            hidden: parent.hidden || data.hidden,
+            grid_read_only: if matches!(data.role, Role::Grid | Role::TreeGrid) {
+                data.read_only
+            } else {
+                parent.grid_read_only
+            },
+            language: data.language.clone().or_else(|| parent.language.clone()),
+            font_family: data
+                .font_family
+                .clone()
+                .or_else(|| parent.font_family.clone()),
+            font_size: data.font_size.or(parent.font_size),
+            font_weight: data.font_weight.or(parent.font_weight),
+            background_color: data.background_color.or(parent.background_color),
+            foreground_color: data.foreground_color.or(parent.foreground_color),
+            text_direction: data.text_direction.or(parent.text_direction),
+            bold: parent.bold || data.bold,
+            italic: parent.italic || data.italic,
+            overline: data.overline.or(parent.overline),
+            strikethrough: data.strikethrough.or(parent.strikethrough),
+            underline: data.underline.or(parent.underline),
+            vertical_offset: data.vertical_offset.or(parent.vertical_offset),
+        }
+    }
+}
+
+/// Returns true if nodes with this role inherit the read-only state
+/// of their grid.
+fn is_grid_cell_role(role: Role) -> bool {
+    matches!(role, Role::Cell | Role::ColumnHeader | Role::RowHeader)
+}
+
+/// Returns true if replacing `old` with `new` may change the values that
+/// a node's descendants inherit or compute from their ancestors.
+pub(crate) fn affects_descendants(old: &NodeData, new: &NodeData) -> bool {
+    old.transform != new.transform
+        || old.live != new.live
+        || old.role != new.role
+        || old.disabled != new.disabled
+        || old.hidden != new.hidden
+        || old.read_only != new.read_only
+        || old.language != new.language
+        || old.font_family != new.font_family
+        || old.font_size != new.font_size
+        || old.font_weight != new.font_weight
+        || old.background_color != new.background_color
+        || old.foreground_color != new.foreground_color
+        || old.text_direction != new.text_direction
+        || old.bold != new.bold
+        || old.italic != new.italic
+        || old.overline != new.overline
+        || old.strikethrough != new.strikethrough
+        || old.underline != new.underline
+        || old.vertical_offset != new.vertical_offset
+}
+
 #[derive(Clone)]
 pub struct NodeState {
     pub(crate) id: NodeId,
     pub(crate) parent_and_index: Option<ParentAndIndex>,
     pub(crate) data: Arc<NodeData>,
+    pub(crate) cache: Arc<NodeCache>,
 }
 
 #[derive(Copy, Clone)]
@@ -51,12 +167,54 @@ impl<'a> Node<'a> {
             is_root: self.is_root(),
             name: self.name(),
             live: self.live(),
+            transform: self.transform(),
+            has_presentational_ancestor: self.has_presentational_ancestor(),
+            inherited: Arc::clone(self.inherited()),
+            is_hidden_by_modal: self.is_hidden_by_modal(),
             supports_text_ranges: self.supports_text_ranges(),
+            is_read_only: self.is_read_only(),
         }
     }
 
     pub fn is_focused(&self) -> bool {
-        self.tree_state.focus == Some(self.id())
+        self.tree_state.focus_id() == Some(self.id())
+    }
+
+    /// Returns true if there's an [active modal](TreeState::modal) and this
+    /// node is outside of it. The modal's ancestors aren't hidden by it.
+    pub fn is_hidden_by_modal(&self) -> bool {
+        let modal = match self.tree_state.modal() {
+            Some(modal) => modal,
+            None => return false,
+        };
+        if let Some(cached) = self.cache.modal_scope.get() {
+            return *cached == ModalScope::Outside;
+        }
+        let mut chain = vec![*self];
+        // The root's parent, if it had one, would be an ancestor of the modal.
+        let mut scope = ModalScope::Ancestor;
+        while let Some(parent) = chain.last().unwrap().parent() {
+            if let Some(cached) = parent.cache.modal_scope.get() {
+                scope = *cached;
+                break;
+            }
+            chain.push(parent);
+        }
+        for node in chain.iter().rev() {
+            // Only the children of the modal's ancestors need to be checked;
+            // every other node is on the same side of the modal as its parent.
+            if scope == ModalScope::Ancestor {
+                scope = if node.id() == modal.id() {
+                    ModalScope::Inside
+                } else if modal.is_descendant_of(node) {
+                    ModalScope::Ancestor
+                } else {
+                    ModalScope::Outside
+                };
+            }
+            let _ = node.cache.modal_scope.set(scope);
+        }
+        scope == ModalScope::Outside
     }
 }
 
@@ -82,6 +240,13 @@ impl NodeState {
             .as_ref()
             .map(|ParentAndIndex(id, _)| *id)
     }
+
+    /// Returns the index of this node within its parent's children.
+    pub fn index_in_parent(&self) -> Option<usize> {
+        self.parent_and_index
+            .as_ref()
+            .map(|ParentAndIndex(_, index)| *index)
+    }
 }
 
 impl<'a> Node<'a> {
@@ -91,13 +256,14 @@ impl<'a> Node<'a> {
     }
 
     pub fn filtered_parent(&self, filter: &impl Fn(&Node) -> FilterResult) -> Option<Node<'a>> {
-        self.parent().and_then(move |parent| {
+        let mut current = self.parent();
+        while let Some(parent) = current {
             if filter(&parent) == FilterResult::Include {
-                Some(parent)
-            } else {
-                parent.filtered_parent(filter)
+                return Some(parent);
             }
-        })
+            current = parent.parent();
+        }
+        None
     }
 
     pub fn parent_and_index(self) -> Option<(Node<'a>, usize)> {
@@ -235,11 +401,12 @@ impl<'a> Node<'a> {
     }
 
     pub fn is_descendant_of(&self, ancestor: &Node) -> bool {
-        if self.id() == ancestor.id() {
-            return true;
-        }
-        if let Some(parent) = self.parent() {
-            return parent.is_descendant_of(ancestor);
+        let mut current = Some(*self);
+        while let Some(node) = current {
+            if node.id() == ancestor.id() {
+                return true;
+            }
+            current = node.parent();
         }
         false
     }
@@ -260,22 +427,48 @@ impl<'a> Node<'a> {
     /// Returns the combined affine transform of this node and its ancestors,
     /// up to and including the root of this node's tree.
     pub fn transform(&self) -> Affine {
-        self.parent()
-            .map_or(Affine::IDENTITY, |parent| parent.transform())
-            * self.direct_transform()
+        if let Some(transform) = self.cache.transform.get() {
+            return *transform;
+        }
+        // Start from the nearest ancestor whose transform is already known,
+        // and cache the transforms of the nodes below it on the way down.
+        let mut chain = vec![*self];
+        let mut transform = Affine::IDENTITY;
+        while let Some(parent) = chain.last().unwrap().parent() {
+            if let Some(parent_transform) = parent.cache.transform.get() {
+                transform = *parent_transform;
+                break;
+            }
+            chain.push(parent);
+        }
+        for node in chain.iter().rev() {
+            transform *= node.direct_transform();
+            let _ = node.cache.transform.set(transform);
+        }
+        transform
     }
 
+    /// Combines the direct transforms of this node and its ancestors,
+    /// excluding `stop_at` and everything above it. The transforms are
+    /// multiplied starting with the outermost ancestor, so the rounding
+    /// is the same as when combining them from the root down.
     pub(crate) fn relative_transform(&self, stop_at: &Node) -> Affine {
-        let parent_transform = if let Some(parent) = self.parent() {
+        if self.id() == stop_at.id() {
+            return Affine::IDENTITY;
+        }
+        let mut chain = vec![*self];
+        while let Some(parent) = chain.last().unwrap().parent() {
             if parent.id() == stop_at.id() {
-                Affine::IDENTITY
-            } else {
-                parent.relative_transform(stop_at)
+                break;
             }
-        } else {
-            Affine::IDENTITY
-        };
-        parent_transform * self.direct_transform()
+            chain.push(parent);
+        }
+        chain
+            .iter()
+            .rev()
+            .fold(Affine::IDENTITY, |transform, node| {
+                transform * node.direct_transform()
+            })
     }
 }
 
@@ -310,6 +503,17 @@ impl<'a> Node<'a> {
         &self,
         point: Point,
         filter: &impl Fn(&Node) -> FilterResult,
+    ) -> Option<(Node<'a>, Point)> {
+        if let Some(index) = &self.tree_state.spatial_index {
+            return index.hit_test(self, point, filter);
+        }
+        self.hit_test_exhaustive(point, filter)
+    }
+
+    pub(crate) fn hit_test_exhaustive(
+        &self,
+        point: Point,
+        filter: &impl Fn(&Node) -> FilterResult,
     ) -> Option<(Node<'a>, Point)> {
         let filter_result = filter(self);
 
@@ -317,19 +521,27 @@ impl<'a> Node<'a> {
             return None;
         }
 
-        for child in self.children().rev() {
-            let point = child.direct_transform().inverse() * point;
-            if let Some(result) = child.hit_test(point, filter) {
-                return Some(result);
+        // Visit the children in reverse order, and each node's descendants
+        // before the node itself.
+        let mut stack = vec![(*self, point, filter_result, self.children().rev())];
+        while let Some((node, point, filter_result, children)) = stack.last_mut() {
+            if let Some(child) = children.next() {
+                let child_filter_result = filter(&child);
+                if child_filter_result != FilterResult::ExcludeSubtree {
+                    let point = child.direct_transform().inverse() * *point;
+                    stack.push((child, point, child_filter_result, child.children().rev()));
+                }
+                continue;
             }
-        }
 
-        if filter_result == FilterResult::Include {
-            if let Some(rect) = &self.state.raw_bounds() {
-                if rect.contains(point) {
-                    return Some((*self, point));
+            if *filter_result == FilterResult::Include {
+                if let Some(rect) = &node.state.raw_bounds() {
+                    if rect.contains(*point) {
+                        return Some((*node, *point));
+                    }
                 }
             }
+            stack.pop();
         }
 
         None
@@ -355,14 +567,55 @@ impl NodeState {
         self.data().role
     }
 
+    /// Returns true if this node itself is hidden. [`Node::is_hidden`],
+    /// which takes precedence when called on a [`Node`], also considers
+    /// its ancestors, so the two may differ.
     pub fn is_hidden(&self) -> bool {
         self.data().hidden
     }
 
+    /// Returns true if this node itself is disabled. [`Node::is_disabled`],
+    /// which takes precedence when called on a [`Node`], also considers
+    /// its ancestors, so the two may differ.
     pub fn is_disabled(&self) -> bool {
         self.data().disabled
     }
 
+    pub fn is_modal(&self) -> bool {
+        self.data().modal
+    }
+
+    /// Returns true if ARIA treats this node's descendants as presentational,
+    /// so that they shouldn't be exposed to assistive technologies.
+    pub fn has_presentational_children(&self) -> bool {
+        matches!(
+            self.role(),
+            Role::Button
+                | Role::CheckBox
+                | Role::DocPageBreak
+                | Role::GraphicsSymbol
+                | Role::Image
+                | Role::ListBoxOption
+                | Role::Math
+                | Role::MenuItemCheckBox
+                | Role::MenuItemRadio
+                | Role::MenuListOption
+                | Role::Meter
+                | Role::ProgressIndicator
+                | Role::RadioButton
+                | Role::ScrollBar
+                | Role::Slider
+                | Role::Splitter
+                | Role::Switch
+                | Role::Tab
+                | Role::ToggleButton
+        )
+    }
+
+    /// Returns true if this node itself is read-only. [`Node::is_read_only`],
+    /// which takes precedence when called on a [`Node`], also considers
+    /// an enclosing read-only grid and synthesized text, so the two
+    /// may differ.
     pub fn is_read_only(&self) -> bool {
         let data = self.data();
         if data.read_only {
@@ -501,6 +754,22 @@ impl NodeState {
     pub fn supports_decrement(&self) -> bool {
         self.supports_action(Action::Decrement)
     }
+
+    pub fn supports_select(&self) -> bool {
+        self.supports_action(Action::Select)
+    }
+
+    pub fn supports_add_to_selection(&self) -> bool {
+        self.supports_action(Action::AddToSelection)
+    }
+
+    pub fn supports_remove_from_selection(&self) -> bool {
+        self.supports_action(Action::RemoveFromSelection)
+    }
+
+    pub fn supports_select_all(&self) -> bool {
+        self.supports_action(Action::SelectAll)
+    }
 }
 
 fn descendant_label_filter(node: &Node) -> FilterResult {
@@ -527,7 +796,10 @@ impl<'a> Node<'a> {
     }
 
     pub fn name(&self) -> Option<String> {
-        if let Some(name) = &self.data().name {
+        if let Some(name) = self.cache.name.get() {
+            return name.clone();
+        }
+        let name = if let Some(name) = &self.data().name {
             Some(name.to_string())
         } else {
             let names = self
@@ -535,7 +807,9 @@ impl<'a> Node<'a> {
                 .filter_map(|node| node.name())
                 .collect::<Vec<String>>();
             (!names.is_empty()).then(move || names.join(" "))
-        }
+        };
+        let _ = self.cache.name.set(name.clone());
+        name
     }
 }
 
@@ -594,10 +868,157 @@ impl NodeState {
 }
 
 impl<'a> Node<'a> {
+    fn inherited(&self) -> &'a Arc<InheritedState> {
+        let state: &'a NodeState = self.state;
+        if let Some(inherited) = state.cache.inherited.get() {
+            return inherited;
+        }
+        let mut chain = vec![*self];
+        let mut inherited = Arc::default();
+        while let Some(parent) = chain.last().unwrap().parent() {
+            if let Some(parent_inherited) = parent.cache.inherited.get() {
+                inherited = Arc::clone(parent_inherited);
+                break;
+            }
+            chain.push(parent);
+        }
+        for node in chain.iter().rev() {
+            let new = InheritedState::new(&inherited, node.data());
+            // Share the ancestor's values when this node doesn't change them,
+            // as most nodes don't.
+            if new != *inherited {
+                inherited = Arc::new(new);
+            }
+            let _ = node.cache.inherited.set(Arc::clone(&inherited));
+        }
+        state.cache.inherited.get().unwrap()
+    }
+
+    /// Returns true if this node or one of its ancestors is disabled.
+    /// [`NodeState::is_disabled`] only considers the node itself.
+    pub fn is_disabled(&self) -> bool {
+        self.inherited().disabled
+    }
+
+    /// Returns true if this node or one of its ancestors is hidden.
+    /// [`NodeState::is_hidden`] only considers the node itself.
+    pub fn is_hidden(&self) -> bool {
+        self.inherited().hidden
+    }
+
+    /// Returns true if this node is read-only, or it's a cell in a grid
+    /// or tree grid that is marked as read-only, as ARIA specifies.
+    /// Text synthesized from a node's name or value is also read-only,
+    /// unless the node is a text field or spin button.
+    /// [`NodeState::is_read_only`] only considers the node itself.
+    pub fn is_read_only(&self) -> bool {
+        self.state.is_read_only()
+            || (is_grid_cell_role(self.role()) && self.inherited().grid_read_only)
+            || (self.has_synthesized_text()
+                && !self.is_text_field()
+                && self.role() != Role::SpinButton)
+    }
+
+    pub fn is_read_only_or_disabled(&self) -> bool {
+        self.is_read_only() || self.is_disabled()
+    }
+
+    pub fn language(&self) -> Option<&'a str> {
+        self.inherited().language.as_deref()
+    }
+
+    pub fn font_family(&self) -> Option<&'a str> {
+        self.inherited().font_family.as_deref()
+    }
+
+    pub fn font_size(&self) -> Option<f32> {
+        self.inherited().font_size
+    }
+
+    pub fn font_weight(&self) -> Option<f32> {
+        self.inherited().font_weight
+    }
+
+    pub fn background_color(&self) -> Option<u32> {
+        self.inherited().background_color
+    }
+
+    pub fn foreground_color(&self) -> Option<u32> {
+        self.inherited().foreground_color
+    }
+
+    pub fn text_direction(&self) -> Option<TextDirection> {
+        self.inherited().text_direction
+    }
+
+    pub fn is_bold(&self) -> bool {
+        self.inherited().bold
+    }
+
+    pub fn is_italic(&self) -> bool {
+        self.inherited().italic
+    }
+
+    pub fn overline(&self) -> Option<TextDecoration> {
+        self.inherited().overline
+    }
+
+    pub fn strikethrough(&self) -> Option<TextDecoration> {
+        self.inherited().strikethrough
+    }
+
+    pub fn underline(&self) -> Option<TextDecoration> {
+        self.inherited().underline
+    }
+
+    pub fn vertical_offset(&self) -> Option<VerticalOffset> {
+        self.inherited().vertical_offset
+    }
+
+    /// Returns true if one of this node's ancestors
+    /// [has presentational children](NodeState::has_presentational_children).
+    pub fn has_presentational_ancestor(&self) -> bool {
+        if let Some(cached) = self.cache.presentational_ancestor.get() {
+            return *cached;
+        }
+        let mut chain = vec![*self];
+        let mut result = false;
+        while let Some(parent) = chain.last().unwrap().parent() {
+            if let Some(cached) = parent.cache.presentational_ancestor.get() {
+                result = *cached;
+                break;
+            }
+            chain.push(parent);
+        }
+        for node in chain.iter().rev() {
+            if let Some(parent) = node.parent() {
+                result = result || parent.has_presentational_children();
+            }
+            let _ = node.cache.presentational_ancestor.set(result);
+        }
+        result
+    }
+
     pub fn live(&self) -> Live {
-        self.data()
-            .live
-            .unwrap_or_else(|| self.parent().map_or(Live::Off, |parent| parent.live()))
+        let mut chain = Vec::new();
+        let mut current = Some(*self);
+        let mut live = Live::Off;
+        while let Some(node) = current {
+            if let Some(cached) = node.cache.live.get() {
+                live = *cached;
+                break;
+            }
+            chain.push(node);
+            if let Some(explicit) = node.data().live {
+                live = explicit;
+                break;
+            }
+            current = node.parent();
+        }
+        for node in chain {
+            let _ = node.cache.live.set(live);
+        }
+        live
     }
 }
 
@@ -606,11 +1027,276 @@ impl NodeState {
         self.data().selected
     }
 
+    pub fn is_selected_from_focus(&self) -> bool {
+        self.data().selected_from_focus
+    }
+
+    pub fn is_multiselectable(&self) -> bool {
+        self.data().multiselectable
+    }
+
+    /// Returns true if this node manages the selection of the items
+    /// inside it, like a list box does for its options.
+    pub fn is_selection_container(&self) -> bool {
+        matches!(
+            self.role(),
+            Role::ComboBoxMenuButton
+                | Role::Grid
+                | Role::ListBox
+                | Role::ListGrid
+                | Role::Menu
+                | Role::MenuBar
+                | Role::MenuListPopup
+                | Role::RadioGroup
+                | Role::TabList
+                | Role::Tree
+                | Role::TreeGrid
+        )
+    }
+
     pub fn raw_text_selection(&self) -> Option<&TextSelection> {
         self.data().text_selection.as_ref()
     }
 }
 
+impl<'a> Node<'a> {
+    /// Returns the nearest ancestor that is a selection container,
+    /// i.e. the node whose selection this node takes part in if it's
+    /// selectable.
+    pub fn selection_container(&self) -> Option<Node<'a>> {
+        let mut current = self.parent();
+        while let Some(node) = current {
+            if node.is_selection_container() {
+                return Some(node);
+            }
+            current = node.parent();
+        }
+        None
+    }
+
+    /// Returns the selected items of this selection container, in tree
+    /// order. The items don't have to be direct children; for example,
+    /// the cells of a grid are inside rows. Items that belong to a nested
+    /// selection container, or that are hidden, aren't included.
+    pub fn selected_children(&self) -> impl Iterator<Item = Node<'a>> {
+        let mut result = Vec::new();
+        if self.is_selection_container() {
+            let mut stack = self.children().rev().collect::<Vec<_>>();
+            while let Some(node) = stack.pop() {
+                if node.is_hidden() {
+                    continue;
+                }
+                if node.is_selected() == Some(true) {
+                    result.push(node);
+                }
+                if !node.is_selection_container() {
+                    stack.extend(node.children().rev());
+                }
+            }
+        }
+        result.into_iter()
+    }
+
+    pub fn selected_count(&self) -> usize {
+        self.selected_children().count()
+    }
+}
+
+/// Returns the roles of the nodes that can contain an ordered set
+/// of items with the given role.
+fn ordered_set_container_roles(item_role: Role) -> &'static [Role] {
+    match item_role {
+        Role::ListItem => &[Role::List],
+        Role::ListBoxOption => &[Role::ListBox],
+        Role::MenuItem | Role::MenuItemCheckBox | Role::MenuItemRadio => {
+            &[Role::Menu, Role::MenuBar]
+        }
+        Role::MenuListOption => &[Role::MenuListPopup],
+        Role::RadioButton => &[Role::RadioGroup],
+        Role::Tab => &[Role::TabList],
+        Role::TreeItem => &[Role::Tree, Role::Group, Role::TreeItem],
+        _ => &[],
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Returns the 1-based position and size of the ordered set that this
+    /// node belongs to. Radio buttons use their `radio_group` if it's set;
+    /// otherwise the set is made of the visible siblings that are the same
+    /// kind of item, inside a parent that can contain them.
+    fn ordered_set_position(&self) -> Option<(usize, usize)> {
+        let container_roles = ordered_set_container_roles(self.role());
+        if container_roles.is_empty() {
+            return None;
+        }
+        let radio_group = &self.state.data().radio_group;
+        if self.role() == Role::RadioButton && !radio_group.is_empty() {
+            let items = radio_group
+                .iter()
+                .filter(|id| self.tree_state.has_node(**id));
+            let index = items.clone().position(|id| *id == self.id())?;
+            return Some((index + 1, items.count()));
+        }
+        let parent = self.parent()?;
+        let index = self.index_in_parent()?;
+        let state: &'a NodeState = parent.state;
+        // Adapters ask for the position of every item, so compute them
+        // for all of the parent's children at once.
+        let positions = state.cache.sibling_set_positions.get_or_init(|| {
+            let mut positions = vec![None; parent.data().children.len()];
+            let mut sets: Vec<(&[Role], Vec<usize>)> = Vec::new();
+            for (index, child) in parent.children().enumerate() {
+                let container_roles = ordered_set_container_roles(child.role());
+                if !container_roles.contains(&parent.role()) || child.is_hidden() {
+                    continue;
+                }
+                match sets.iter_mut().find(|(roles, _)| *roles == container_roles) {
+                    Some((_, indices)) => indices.push(index),
+                    None => sets.push((container_roles, vec![index])),
+                }
+            }
+            for (_, indices) in sets {
+                let size = indices.len();
+                for (position, index) in indices.into_iter().enumerate() {
+                    positions[index] = Some((position + 1, size));
+                }
+            }
+            positions
+        });
+        positions.get(index).copied().flatten()
+    }
+
+    /// Returns the 1-based position of this node in its ordered set,
+    /// such as a list or a radio group. This is the explicit
+    /// `pos_in_set` if there is one, and is computed otherwise.
+    pub fn pos_in_set(&self) -> Option<usize> {
+        self.data()
+            .pos_in_set
+            .or_else(|| Some(self.ordered_set_position()?.0))
+    }
+
+    /// Returns the number of items in this node's ordered set. This is the
+    /// explicit `set_size` if there is one, and is computed otherwise.
+    pub fn set_size(&self) -> Option<usize> {
+        self.data()
+            .set_size
+            .or_else(|| Some(self.ordered_set_position()?.1))
+    }
+
+    /// Returns the 1-based nesting level of this node. This is the explicit
+    /// `hierarchical_level` if there is one; otherwise it's only computed
+    /// for tree items, from the number of tree items that contain them.
+    pub fn hierarchical_level(&self) -> Option<usize> {
+        if let Some(level) = self.data().hierarchical_level {
+            return Some(level);
+        }
+        if self.role() != Role::TreeItem {
+            return None;
+        }
+        let mut level = 1;
+        let mut current = self.parent();
+        while let Some(node) = current {
+            match node.role() {
+                Role::Tree => break,
+                Role::TreeItem => level += 1,
+                _ => (),
+            }
+            current = node.parent();
+        }
+        Some(level)
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Returns true if keyboard focus can move to this node: it's focusable,
+    /// and neither it nor any of its ancestors is hidden or disabled,
+    /// and it isn't [hidden by a modal](Node::is_hidden_by_modal).
+    pub fn can_receive_focus(&self) -> bool {
+        self.is_focusable()
+            && !self.is_hidden()
+            && !self.is_disabled()
+            && !self.is_hidden_by_modal()
+    }
+
+    /// Returns true if none of this node's descendants can receive focus
+    /// because of this node itself.
+    fn is_focus_barrier(&self) -> bool {
+        self.state.is_hidden() || self.state.is_disabled() || self.is_hidden_by_modal()
+    }
+
+    /// Returns the node that keyboard focus moves to from this one when
+    /// navigating forward, as with the Tab key. This is the node's
+    /// `next_focus` if that can receive focus; otherwise it's the next
+    /// node in tree order that can. Returns `None` at the end of the tree,
+    /// so callers decide whether to wrap around.
+    pub fn next_in_focus_order(&self) -> Option<Node<'a>> {
+        if let Some(next) = self.explicit_focus_link(self.data().next_focus) {
+            return Some(next);
+        }
+        let mut current = *self;
+        let mut skip_children = self.is_focus_barrier();
+        loop {
+            let next = if skip_children {
+                None
+            } else {
+                current.children().next()
+            };
+            current = match next {
+                Some(child) => child,
+                None => current.next_after_subtree()?,
+            };
+            if current.can_receive_focus() {
+                return Some(current);
+            }
+            skip_children = current.is_focus_barrier();
+        }
+    }
+
+    /// Returns the node that keyboard focus moves to from this one when
+    /// navigating backward, as with Shift+Tab. This is the node's
+    /// `previous_focus` if that can receive focus; otherwise it's the
+    /// previous node in tree order that can.
+    pub fn previous_in_focus_order(&self) -> Option<Node<'a>> {
+        if let Some(previous) = self.explicit_focus_link(self.data().previous_focus) {
+            return Some(previous);
+        }
+        let mut current = *self;
+        loop {
+            current = match current.preceding_siblings().next() {
+                Some(mut sibling) => {
+                    while !sibling.is_focus_barrier() {
+                        match sibling.children().next_back() {
+                            Some(child) => sibling = child,
+                            None => break,
+                        }
+                    }
+                    sibling
+                }
+                None => current.parent()?,
+            };
+            if current.can_receive_focus() {
+                return Some(current);
+            }
+        }
+    }
+
+    fn explicit_focus_link(&self, id: Option<NodeId>) -> Option<Node<'a>> {
+        id.and_then(|id| self.tree_state.node_by_id(id))
+            .filter(|node| node.can_receive_focus())
+    }
+
+    /// Returns the first node after this node's subtree in tree order.
+    fn next_after_subtree(&self) -> Option<Node<'a>> {
+        let mut current = *self;
+        loop {
+            if let Some(sibling) = current.following_siblings().next() {
+                return Some(sibling);
+            }
+            current = current.parent()?;
+        }
+    }
+}
+
 impl<'a> Node<'a> {
     pub fn index_path(&self) -> Vec<usize> {
         self.relative_index_path(self.tree_state.root_id())
@@ -632,15 +1318,16 @@ impl<'a> Node<'a> {
         &self,
         filter: &impl Fn(&Node) -> FilterResult,
     ) -> Option<Node<'a>> {
-        for child in self.children() {
-            let result = filter(&child);
-            if result == FilterResult::Include {
-                return Some(child);
-            }
-            if result == FilterResult::ExcludeNode {
-                if let Some(descendant) = child.first_filtered_child(filter) {
-                    return Some(descendant);
+        let mut stack = vec![self.children()];
+        while let Some(children) = stack.last_mut() {
+            if let Some(child) = children.next() {
+                match filter(&child) {
+                    FilterResult::Include => return Some(child),
+                    FilterResult::ExcludeNode => stack.push(child.children()),
+                    FilterResult::ExcludeSubtree => {}
                 }
+            } else {
+                stack.pop();
             }
         }
         None
@@ -650,15 +1337,16 @@ impl<'a> Node<'a> {
         &self,
         filter: &impl Fn(&Node) -> FilterResult,
     ) -> Option<Node<'a>> {
-        for child in self.children().rev() {
-            let result = filter(&child);
-            if result == FilterResult::Include {
-                return Some(child);
-            }
-            if result == FilterResult::ExcludeNode {
-                if let Some(descendant) = child.last_filtered_child(filter) {
-                    return Some(descendant);
+        let mut stack = vec![self.children().rev()];
+        while let Some(children) = stack.last_mut() {
+            if let Some(child) = children.next() {
+                match filter(&child) {
+                    FilterResult::Include => return Some(child),
+                    FilterResult::ExcludeNode => stack.push(child.children().rev()),
+                    FilterResult::ExcludeSubtree => {}
                 }
+            } else {
+                stack.pop();
             }
         }
         None
@@ -684,7 +1372,12 @@ pub struct DetachedNode {
     pub(crate) is_root: bool,
     pub(crate) name: Option<String>,
     pub(crate) live: Live,
+    pub(crate) transform: Affine,
+    pub(crate) has_presentational_ancestor: bool,
+    pub(crate) inherited: Arc<InheritedState>,
+    pub(crate) is_hidden_by_modal: bool,
     pub(crate) supports_text_ranges: bool,
+    pub(crate) is_read_only: bool,
 }
 
 impl DetachedNode {
@@ -704,6 +1397,86 @@ impl DetachedNode {
         self.live
     }
 
+    pub fn transform(&self) -> Affine {
+        self.transform
+    }
+
+    pub fn has_presentational_ancestor(&self) -> bool {
+        self.has_presentational_ancestor
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.inherited.disabled
+    }
+
+    pub fn is_hidden_by_modal(&self) -> bool {
+        self.is_hidden_by_modal
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.inherited.hidden
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.is_read_only
+    }
+
+    pub fn is_read_only_or_disabled(&self) -> bool {
+        self.is_read_only() || self.is_disabled()
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.inherited.language.as_deref()
+    }
+
+    pub fn font_family(&self) -> Option<&str> {
+        self.inherited.font_family.as_deref()
+    }
+
+    pub fn font_size(&self) -> Option<f32> {
+        self.inherited.font_size
+    }
+
+    pub fn font_weight(&self) -> Option<f32> {
+        self.inherited.font_weight
+    }
+
+    pub fn background_color(&self) -> Option<u32> {
+        self.inherited.background_color
+    }
+
+    pub fn foreground_color(&self) -> Option<u32> {
+        self.inherited.foreground_color
+    }
+
+    pub fn text_direction(&self) -> Option<TextDirection> {
+        self.inherited.text_direction
+    }
+
+    pub fn is_bold(&self) -> bool {
+        self.inherited.bold
+    }
+
+    pub fn is_italic(&self) -> bool {
+        self.inherited.italic
+    }
+
+    pub fn overline(&self) -> Option<TextDecoration> {
+        self.inherited.overline
+    }
+
+    pub fn strikethrough(&self) -> Option<TextDecoration> {
+        self.inherited.strikethrough
+    }
+
+    pub fn underline(&self) -> Option<TextDecoration> {
+        self.inherited.underline
+    }
+
+    pub fn vertical_offset(&self) -> Option<VerticalOffset> {
+        self.inherited.vertical_offset
+    }
+
     pub fn supports_text_ranges(&self) -> bool {
         self.supports_text_ranges
     }
@@ -723,8 +1496,8 @@ impl Deref for DetachedNode {
 
 #[cfg(test)]
 mod tests {
-    use accesskit::kurbo::{Point, Rect};
-    use accesskit::{Node, NodeId, Role, Tree, TreeUpdate};
+    use accesskit::kurbo::{Affine, Point, Rect, Vec2};
+    use accesskit::{Live, Node, NodeId, Role, Tree, TreeUpdate};
     use std::{num::NonZeroU128, sync::Arc};
 
     use crate::tests::*;
@@ -1153,4 +1926,557 @@ mod tests {
             tree.read().node_by_id(NODE_ID_4).unwrap().name()
         );
     }
+
+    #[test]
+    fn deep_tree() {
+        const DEPTH: u128 = 50_000;
+        let tree = crate::tests::deep_tree(DEPTH);
+        let leaf_point = Point::new(DEPTH as f64 + 4.0, 5.0);
+        {
+            let state = tree.read();
+            let root = state.root();
+            let leaf = state.node_by_id(deep_tree_node_id(DEPTH + 1)).unwrap();
+            assert!(leaf.is_descendant_of(&root));
+            assert!(!root.is_descendant_of(&leaf));
+            assert_eq!(
+                Some(root.id()),
+                leaf.filtered_parent(&deep_tree_filter)
+                    .map(|node| node.id())
+            );
+            assert_eq!(Live::Polite, leaf.live());
+            assert_eq!(
+                Affine::translate(Vec2::new((DEPTH - 1) as f64, 0.0)),
+                leaf.transform()
+            );
+            assert_eq!(
+                Some(Rect::new(DEPTH as f64 - 1.0, 0.0, DEPTH as f64 + 9.0, 10.0)),
+                leaf.bounding_box()
+            );
+            assert_eq!(DEPTH as usize, leaf.index_path().len());
+            assert_eq!(
+                Some(leaf.id()),
+                root.first_filtered_child(&deep_tree_filter)
+                    .map(|node| node.id())
+            );
+            assert_eq!(
+                Some(leaf.id()),
+                root.last_filtered_child(&deep_tree_filter)
+                    .map(|node| node.id())
+            );
+            assert_eq!(
+                Some(leaf.id()),
+                root.node_at_point(leaf_point, &deep_tree_filter)
+                    .map(|node| node.id())
+            );
+        }
+        tree.set_spatial_index_enabled(true);
+        assert_eq!(
+            Some(deep_tree_node_id(DEPTH + 1)),
+            tree.read()
+                .root()
+                .node_at_point(leaf_point, &deep_tree_filter)
+                .map(|node| node.id())
+        );
+    }
+
+    fn assert_cached_values_are_fresh(state: &crate::TreeState) {
+        let fresh_tree = crate::Tree::new(state.serialize(), Box::new(NullActionHandler {}));
+        let fresh_state = fresh_tree.read();
+        for id in state.nodes.keys() {
+            let node = state.node_by_id(*id).unwrap();
+            let fresh_node = fresh_state.node_by_id(*id).unwrap();
+            assert_eq!(fresh_node.name(), node.name());
+            assert_eq!(fresh_node.live(), node.live());
+            assert_eq!(fresh_node.transform(), node.transform());
+            assert_eq!(
+                fresh_node.has_presentational_ancestor(),
+                node.has_presentational_ancestor()
+            );
+            assert_eq!(fresh_node.is_hidden_by_modal(), node.is_hidden_by_modal());
+            assert_eq!(fresh_node.pos_in_set(), node.pos_in_set());
+            assert_eq!(fresh_node.set_size(), node.set_size());
+            assert!(fresh_node.inherited() == node.inherited());
+            assert!(fresh_node.synthesized_text() == node.synthesized_text());
+        }
+    }
+
+    #[test]
+    fn inherited_state() {
+        let node_id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let window = |group_language: &str| {
+            Arc::new(Node {
+                role: Role::Window,
+                children: vec![node_id(2)],
+                language: Some(group_language.into()),
+                font_size: Some(12.0),
+                ..Default::default()
+            })
+        };
+        let group = Arc::new(Node {
+            role: Role::Group,
+            children: vec![node_id(3)],
+            disabled: true,
+            read_only: true,
+            font_size: Some(16.0),
+            ..Default::default()
+        });
+        let text_field = Arc::new(Node {
+            role: Role::TextField,
+            language: Some("fr".into()),
+            ..Default::default()
+        });
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (node_id(1), window("en")),
+                    (node_id(2), group),
+                    (node_id(3), text_field.clone()),
+                ],
+                tree: Some(Tree::new(node_id(1))),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+        let root = state.root();
+        assert!(!root.is_disabled());
+        assert_eq!(Some("en"), root.language());
+        let text_field_node = state.node_by_id(node_id(3)).unwrap();
+        assert!(!text_field_node.state().is_disabled());
+        assert!(text_field_node.is_disabled());
+        // ARIA only propagates read-only from a grid to its cells.
+        assert!(!text_field_node.is_read_only());
+        assert!(!text_field_node.is_hidden());
+        assert_eq!(Some("fr"), text_field_node.language());
+        assert_eq!(Some(16.0), text_field_node.font_size());
+        assert_eq!(None, text_field_node.font_family());
+        let detached = text_field_node.detached();
+        assert!(detached.is_disabled());
+        assert_eq!(Some("fr"), detached.language());
+        assert_eq!(Some(16.0), detached.font_size());
+        drop(state);
+
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                node_id(2),
+                Arc::new(Node {
+                    role: Role::Group,
+                    children: vec![node_id(3)],
+                    ..Default::default()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        let state = tree.read();
+        let text_field_node = state.node_by_id(node_id(3)).unwrap();
+        assert!(!text_field_node.is_disabled());
+        assert!(!text_field_node.is_read_only());
+        assert_eq!(Some(12.0), text_field_node.font_size());
+        assert_cached_values_are_fresh(&state);
+    }
+
+    #[test]
+    fn read_only_grid_cells() {
+        let node_id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let grid = |read_only| {
+            Arc::new(Node {
+                role: Role::Grid,
+                children: vec![node_id(2)],
+                read_only,
+                ..Default::default()
+            })
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (node_id(1), grid(true)),
+                    (
+                        node_id(2),
+                        Arc::new(Node {
+                            role: Role::Row,
+                            children: vec![node_id(3)],
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        node_id(3),
+                        Arc::new(Node {
+                            role: Role::Cell,
+                            children: vec![node_id(4)],
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        node_id(4),
+                        Arc::new(Node {
+                            role: Role::TextField,
+                            editable: true,
+                            ..Default::default()
+                        }),
+                    ),
+                ],
+                tree: Some(Tree::new(node_id(1))),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+        let cell = state.node_by_id(node_id(3)).unwrap();
+        assert!(!cell.state().is_read_only());
+        assert!(cell.is_read_only());
+        assert!(cell.detached().is_read_only());
+        assert!(!state.node_by_id(node_id(2)).unwrap().is_read_only());
+        assert!(!state.node_by_id(node_id(4)).unwrap().is_read_only());
+        drop(state);
+
+        tree.update(TreeUpdate {
+            nodes: vec![(node_id(1), grid(false))],
+            tree: None,
+            focus: None,
+        });
+        let state = tree.read();
+        assert!(!state.node_by_id(node_id(3)).unwrap().is_read_only());
+        assert_cached_values_are_fresh(&state);
+    }
+
+    #[test]
+    fn cached_values_follow_updates() {
+        let node_id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let root = Node {
+            role: Role::Window,
+            children: vec![node_id(2), node_id(3), node_id(4)],
+            ..Default::default()
+        };
+        let container = Node {
+            role: Role::GenericContainer,
+            transform: Some(Box::new(Affine::translate(Vec2::new(10.0, 0.0)))),
+            children: vec![node_id(5)],
+            ..Default::default()
+        };
+        let button = Node {
+            role: Role::Button,
+            children: vec![node_id(6)],
+            ..Default::default()
+        };
+        let button_text = Node {
+            role: Role::StaticText,
+            name: Some("foo".into()),
+            ..Default::default()
+        };
+        let update = TreeUpdate {
+            nodes: vec![
+                (node_id(1), Arc::new(root.clone())),
+                (node_id(2), Arc::new(container.clone())),
+                (node_id(5), Arc::new(button.clone())),
+                (node_id(6), Arc::new(button_text.clone())),
+                (
+                    node_id(3),
+                    Arc::new(Node {
+                        role: Role::StaticText,
+                        name: Some("bar".into()),
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    node_id(4),
+                    Arc::new(Node {
+                        role: Role::CheckBox,
+                        labelled_by: vec![node_id(3), node_id(5)],
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: Some(Tree::new(node_id(1))),
+            focus: None,
+        };
+        let tree = crate::Tree::new(update, Box::new(NullActionHandler {}));
+        let snapshot = tree.snapshot();
+        assert_cached_values_are_fresh(&snapshot);
+        let name = |id| tree.read().node_by_id(id).unwrap().name();
+
+        // Renaming a descendant label changes the name of the ancestor
+        // that it labels, and of the node that's labelled by that ancestor.
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                node_id(6),
+                Arc::new(Node {
+                    name: Some("baz".into()),
+                    ..button_text.clone()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        assert_eq!(Some("baz".into()), name(node_id(5)));
+        assert_eq!(Some("bar baz".into()), name(node_id(4)));
+        assert_cached_values_are_fresh(&tree.read());
+        assert_eq!(
+            Some("bar foo".into()),
+            snapshot.node_by_id(node_id(4)).unwrap().name()
+        );
+
+        // Changing an ancestor's transform and live setting changes
+        // those of its descendants.
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                node_id(2),
+                Arc::new(Node {
+                    transform: Some(Box::new(Affine::translate(Vec2::new(20.0, 0.0)))),
+                    live: Some(Live::Assertive),
+                    ..container
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        {
+            let state = tree.read();
+            let text = state.node_by_id(node_id(6)).unwrap();
+            assert_eq!(Affine::translate(Vec2::new(20.0, 0.0)), text.transform());
+            assert_eq!(Live::Assertive, text.live());
+        }
+        assert_cached_values_are_fresh(&tree.read());
+
+        // Moving the label out of the button changes the names that came
+        // from it, as well as its own transform and live setting.
+        tree.update(TreeUpdate {
+            nodes: vec![
+                (
+                    node_id(5),
+                    Arc::new(Node {
+                        children: vec![],
+                        ..button
+                    }),
+                ),
+                (
+                    node_id(1),
+                    Arc::new(Node {
+                        children: vec![node_id(2), node_id(3), node_id(4), node_id(6)],
+                        ..root
+                    }),
+                ),
+            ],
+            tree: None,
+            focus: None,
+        });
+        assert_eq!(None, name(node_id(5)));
+        assert_eq!(Some("bar".into()), name(node_id(4)));
+        {
+            let state = tree.read();
+            let text = state.node_by_id(node_id(6)).unwrap();
+            assert_eq!(Affine::IDENTITY, text.transform());
+            assert_eq!(Live::Off, text.live());
+        }
+        assert_cached_values_are_fresh(&tree.read());
+    }
+
+    #[test]
+    fn ordered_sets() {
+        let node_id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let node = |role, children: Vec<NodeId>| Node {
+            role,
+            children,
+            ..Default::default()
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        node_id(1),
+                        Arc::new(node(Role::Window, vec![node_id(2), node_id(6), node_id(9)])),
+                    ),
+                    // A list with a hidden item and a separator.
+                    (
+                        node_id(2),
+                        Arc::new(node(
+                            Role::List,
+                            vec![node_id(3), node_id(4), node_id(5), node_id(10)],
+                        )),
+                    ),
+                    (node_id(3), Arc::new(node(Role::ListItem, vec![]))),
+                    (
+                        node_id(4),
+                        Arc::new(Node {
+                            hidden: true,
+                            ..node(Role::ListItem, vec![])
+                        }),
+                    ),
+                    (node_id(5), Arc::new(node(Role::Splitter, vec![]))),
+                    (node_id(10), Arc::new(node(Role::ListItem, vec![]))),
+                    // A tree with a nested group.
+                    (node_id(6), Arc::new(node(Role::Tree, vec![node_id(7)]))),
+                    (node_id(7), Arc::new(node(Role::TreeItem, vec![node_id(8)]))),
+                    (node_id(8), Arc::new(node(Role::Group, vec![node_id(11)]))),
+                    (
+                        node_id(11),
+                        Arc::new(Node {
+                            pos_in_set: Some(4),
+                            set_size: Some(7),
+                            ..node(Role::TreeItem, vec![])
+                        }),
+                    ),
+                    // Radio buttons that are grouped explicitly.
+                    (
+                        node_id(9),
+                        Arc::new(node(
+                            Role::Group,
+                            vec![node_id(12), node_id(13), node_id(14)],
+                        )),
+                    ),
+                    (
+                        node_id(12),
+                        Arc::new(Node {
+                            radio_group: vec![node_id(14), node_id(12)],
+                            ..node(Role::RadioButton, vec![])
+                        }),
+                    ),
+                    (node_id(13), Arc::new(node(Role::RadioButton, vec![]))),
+                    (
+                        node_id(14),
+                        Arc::new(Node {
+                            radio_group: vec![node_id(14), node_id(12)],
+                            ..node(Role::RadioButton, vec![])
+                        }),
+                    ),
+                ],
+                tree: Some(Tree::new(node_id(1))),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+        let set_info = |n| {
+            let node = state.node_by_id(node_id(n)).unwrap();
+            (
+                node.pos_in_set(),
+                node.set_size(),
+                node.hierarchical_level(),
+            )
+        };
+        assert_eq!((Some(1), Some(2), None), set_info(3));
+        assert_eq!((None, None, None), set_info(4));
+        assert_eq!((None, None, None), set_info(5));
+        assert_eq!((Some(2), Some(2), None), set_info(10));
+        assert_eq!((Some(1), Some(1), Some(1)), set_info(7));
+        assert_eq!((Some(4), Some(7), Some(2)), set_info(11));
+        assert_eq!((Some(2), Some(2), None), set_info(12));
+        assert_eq!((None, None, None), set_info(13));
+        assert_eq!((Some(1), Some(2), None), set_info(14));
+        drop(state);
+
+        // The positions are cached by the parent, so they must follow
+        // changes to its children.
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                node_id(3),
+                Arc::new(Node {
+                    hidden: true,
+                    ..node(Role::ListItem, vec![])
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        let state = tree.read();
+        let item = state.node_by_id(node_id(10)).unwrap();
+        assert_eq!((Some(1), Some(1)), (item.pos_in_set(), item.set_size()));
+        assert_cached_values_are_fresh(&state);
+    }
+
+    #[test]
+    fn focus_order() {
+        let node_id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let button = |next_focus| {
+            Arc::new(Node {
+                role: Role::Button,
+                focusable: true,
+                next_focus,
+                ..Default::default()
+            })
+        };
+        let container = |children, modal| {
+            Arc::new(Node {
+                role: Role::GenericContainer,
+                children,
+                modal,
+                ..Default::default()
+            })
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        node_id(1),
+                        container(
+                            vec![node_id(2), node_id(3), node_id(5), node_id(7), node_id(8)],
+                            false,
+                        ),
+                    ),
+                    (node_id(2), button(None)),
+                    (
+                        node_id(3),
+                        Arc::new(Node {
+                            role: Role::Group,
+                            children: vec![node_id(4)],
+                            hidden: true,
+                            ..Default::default()
+                        }),
+                    ),
+                    (node_id(4), button(None)),
+                    (
+                        node_id(5),
+                        Arc::new(Node {
+                            role: Role::Group,
+                            children: vec![node_id(6)],
+                            disabled: true,
+                            ..Default::default()
+                        }),
+                    ),
+                    (node_id(6), button(None)),
+                    (node_id(7), button(Some(node_id(2)))),
+                    (node_id(8), container(vec![node_id(9), node_id(10)], false)),
+                    (node_id(9), button(None)),
+                    (
+                        node_id(10),
+                        Arc::new(Node {
+                            role: Role::Button,
+                            ..Default::default()
+                        }),
+                    ),
+                ],
+                tree: Some(Tree::new(node_id(1))),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let next = |n| {
+            let state = tree.read();
+            let node = state.node_by_id(node_id(n)).unwrap();
+            node.next_in_focus_order().map(|node| node.id())
+        };
+        let previous = |n| {
+            let state = tree.read();
+            let node = state.node_by_id(node_id(n)).unwrap();
+            node.previous_in_focus_order().map(|node| node.id())
+        };
+        assert_eq!(Some(node_id(2)), next(1));
+        assert_eq!(Some(node_id(7)), next(2));
+        assert_eq!(Some(node_id(2)), next(7));
+        assert_eq!(Some(node_id(9)), next(8));
+        assert_eq!(None, next(9));
+        assert_eq!(Some(node_id(7)), previous(9));
+        assert_eq!(Some(node_id(2)), previous(7));
+        assert_eq!(None, previous(2));
+
+        // A modal confines the focus to its subtree.
+        tree.update(TreeUpdate {
+            nodes: vec![(node_id(8), container(vec![node_id(9), node_id(10)], true))],
+            tree: None,
+            focus: None,
+        });
+        assert_eq!(Some(node_id(9)), next(2));
+        assert_eq!(None, previous(9));
+        assert_eq!(Some(node_id(9)), next(7));
+    }
 }
@@ -0,0 +1,536 @@
+// Copyright 2023 The AccessKit Authors. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (found in
+// the LICENSE-APACHE file) or the MIT license (found in
+// the LICENSE-MIT file), at your option.
+
+use accesskit::kurbo::{Affine, Point, Rect};
+use accesskit::{NodeId, TreeUpdate};
+use rstar::{RTree, RTreeObject, AABB};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{FilterResult, Node, TreeState};
+
+type Envelope = AABB<[f64; 2]>;
+
+#[derive(Clone, PartialEq)]
+struct Entry {
+    id: NodeId,
+    envelope: Envelope,
+}
+
+impl RTreeObject for Entry {
+    type Envelope = Envelope;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+/// The nodes whose transformed bounds may have changed during a tree update.
+#[derive(Default)]
+pub(crate) struct PendingChanges {
+    /// Nodes whose own transform or parent changed, which affects
+    /// the transformed bounds of all of their descendants.
+    subtrees: Vec<NodeId>,
+    /// Nodes that were added or whose bounds changed.
+    nodes: Vec<NodeId>,
+    pub(crate) removed: Vec<NodeId>,
+}
+
+/// An R-tree of the transformed bounding boxes of all nodes that have bounds,
+/// relative to the tree's container (e.g. window).
+///
+/// The index is only used to narrow down the candidates for a hit test.
+/// Each candidate is then checked with the same arithmetic as the exhaustive
+/// depth-first search, so the results are identical.
+#[derive(Clone, Default)]
+pub(crate) struct SpatialIndex {
+    tree: RTree<Entry>,
+    envelopes: HashMap<NodeId, Envelope>,
+    // Nodes whose transformed bounds aren't finite can't be stored
+    // in the R-tree, so they're always considered during hit testing.
+    unindexed: HashSet<NodeId>,
+}
+
+fn envelope_from_rect(rect: Rect) -> Option<Envelope> {
+    let coords = [rect.x0, rect.y0, rect.x1, rect.y1];
+    if !coords.iter().all(|c| c.is_finite()) {
+        return None;
+    }
+    // Pad the box slightly, so that rounding differences between the full
+    // transform used here and the chain of inverse transforms applied
+    // during hit testing can't exclude a node that the search would find.
+    let margin = coords.iter().fold(1.0f64, |max, c| max.max(c.abs())) * 1e-9;
+    Some(AABB::from_corners(
+        [rect.x0.min(rect.x1) - margin, rect.y0.min(rect.y1) - margin],
+        [rect.x0.max(rect.x1) + margin, rect.y0.max(rect.y1) + margin],
+    ))
+}
+
+impl SpatialIndex {
+    pub(crate) fn new(state: &TreeState) -> Self {
+        let mut entries = Vec::new();
+        let mut unindexed = HashSet::new();
+        let mut stack = vec![(state.root(), Affine::IDENTITY)];
+        while let Some((node, parent_transform)) = stack.pop() {
+            let transform = parent_transform * node.direct_transform();
+            if let Some(bounds) = node.raw_bounds() {
+                match envelope_from_rect(transform.transform_rect_bbox(bounds)) {
+                    Some(envelope) => entries.push(Entry {
+                        id: node.id(),
+                        envelope,
+                    }),
+                    None => {
+                        unindexed.insert(node.id());
+                    }
+                }
+            }
+            stack.extend(node.children().map(|child| (child, transform)));
+        }
+        let envelopes = entries
+            .iter()
+            .map(|entry| (entry.id, entry.envelope))
+            .collect();
+        Self {
+            tree: RTree::bulk_load(entries),
+            envelopes,
+            unindexed,
+        }
+    }
+
+    /// Determines which nodes may need to be reindexed after applying
+    /// the given update. This must be called before the update is applied.
+    pub(crate) fn pending_changes(state: &TreeState, update: &TreeUpdate) -> PendingChanges {
+        let mut changes = PendingChanges::default();
+        if let Some(tree) = &update.tree {
+            if state.has_node(tree.root) && tree.root != state.root_id() {
+                changes.subtrees.push(tree.root);
+            }
+        }
+        for (id, data) in &update.nodes {
+            if let Some(old_node) = state.node_by_id(*id) {
+                let old_data = old_node.data();
+                if old_data.transform != data.transform {
+                    changes.subtrees.push(*id);
+                } else if old_data.bounds != data.bounds {
+                    changes.nodes.push(*id);
+                }
+            } else {
+                changes.nodes.push(*id);
+            }
+            for child_id in data.children.iter() {
+                if let Some(child) = state.node_by_id(*child_id) {
+                    if child.parent_id() != Some(*id) {
+                        changes.subtrees.push(*child_id);
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    fn remove(&mut self, id: NodeId) {
+        if let Some(envelope) = self.envelopes.remove(&id) {
+            self.tree.remove(&Entry { id, envelope });
+        }
+        self.unindexed.remove(&id);
+    }
+
+    fn reindex_node(&mut self, node: &Node, transform: Affine) {
+        let id = node.id();
+        self.remove(id);
+        if let Some(bounds) = node.raw_bounds() {
+            match envelope_from_rect(transform.transform_rect_bbox(bounds)) {
+                Some(envelope) => {
+                    self.tree.insert(Entry { id, envelope });
+                    self.envelopes.insert(id, envelope);
+                }
+                None => {
+                    self.unindexed.insert(id);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn apply_changes(&mut self, state: &TreeState, changes: &PendingChanges) {
+        for id in &changes.removed {
+            self.remove(*id);
+        }
+        for id in &changes.subtrees {
+            let node = match state.node_by_id(*id) {
+                Some(node) => node,
+                None => continue,
+            };
+            let parent_transform = node
+                .parent()
+                .map_or(Affine::IDENTITY, |parent| parent.transform());
+            let mut stack = vec![(node, parent_transform)];
+            while let Some((node, parent_transform)) = stack.pop() {
+                let transform = parent_transform * node.direct_transform();
+                self.reindex_node(&node, transform);
+                stack.extend(node.children().map(|child| (child, transform)));
+            }
+        }
+        for id in &changes.nodes {
+            if let Some(node) = state.node_by_id(*id) {
+                self.reindex_node(&node, node.transform());
+            }
+        }
+    }
+
+    /// Returns the same result as an exhaustive hit test starting at `root`,
+    /// with `point` in `root`'s coordinate space.
+    pub(crate) fn hit_test<'a>(
+        &self,
+        root: &Node<'a>,
+        point: Point,
+        filter: &impl Fn(&Node) -> FilterResult,
+    ) -> Option<(Node<'a>, Point)> {
+        let state = root.tree_state;
+        let container_point = root.transform() * point;
+        let query = AABB::from_point([container_point.x, container_point.y]);
+        let candidates = self
+            .tree
+            .locate_in_envelope_intersecting(&query)
+            .map(|entry| entry.id)
+            .chain(self.unindexed.iter().copied());
+        let mut best: Option<(Vec<usize>, Node<'a>, Point)> = None;
+        for id in candidates {
+            let node = match state.node_by_id(id) {
+                Some(node) => node,
+                None => continue,
+            };
+            if let Some((path, local_point)) = hit_path(root, node, point, filter) {
+                let is_best = match &best {
+                    Some((best_path, _, _)) => is_hit_first(&path, best_path),
+                    None => true,
+                };
+                if is_best {
+                    best = Some((path, node, local_point));
+                }
+            }
+        }
+        best.map(|(_, node, point)| (node, point))
+    }
+}
+
+/// The index belonging to the previous version of the tree state, along with
+/// the changes that bring it up to date with the current version.
+///
+/// Readers may still be using the current version while the next one is
+/// being built, so its index can't be modified in place. Instead, once
+/// nothing refers to the previous version anymore, its index is caught up
+/// and reused, so that an update normally doesn't have to copy the index.
+#[derive(Default)]
+pub(crate) struct SpareIndex {
+    spare: Option<(Arc<SpatialIndex>, PendingChanges)>,
+    current: Option<Arc<SpatialIndex>>,
+}
+
+impl SpareIndex {
+    /// Called with a copy of the current state before updating it.
+    pub(crate) fn prepare(&mut self, state: &mut TreeState) {
+        self.current = state.spatial_index.clone();
+        let current = match &self.current {
+            Some(current) => current,
+            None => {
+                self.spare = None;
+                return;
+            }
+        };
+        if let Some((mut spare, changes)) = self.spare.take() {
+            if let Some(index) = Arc::get_mut(&mut spare) {
+                index.apply_changes(state, &changes);
+                debug_assert!(!Arc::ptr_eq(&spare, current));
+                state.spatial_index = Some(spare);
+            }
+        }
+    }
+
+    /// Called after updating the state, with the changes that were applied
+    /// to its index.
+    pub(crate) fn finish(&mut self, changes: Option<PendingChanges>) {
+        self.spare = self.current.take().zip(changes);
+    }
+}
+
+/// If the exhaustive search starting at `root` would accept `node`,
+/// returns the index path from `root` to `node` and the point in `node`'s
+/// coordinate space.
+fn hit_path(
+    root: &Node,
+    node: Node,
+    point: Point,
+    filter: &impl Fn(&Node) -> FilterResult,
+) -> Option<(Vec<usize>, Point)> {
+    if filter(&node) != FilterResult::Include {
+        return None;
+    }
+    let mut path = Vec::new();
+    let mut chain = Vec::new();
+    let mut current = node;
+    while current.id() != root.id() {
+        let (parent, index) = current.parent_and_index()?;
+        if filter(&parent) == FilterResult::ExcludeSubtree {
+            return None;
+        }
+        path.push(index);
+        chain.push(current);
+        current = parent;
+    }
+    path.reverse();
+    let mut point = point;
+    for node in chain.iter().rev() {
+        point = node.direct_transform().inverse() * point;
+    }
+    let rect = node.raw_bounds()?;
+    rect.contains(point).then_some((path, point))
+}
+
+/// The exhaustive search visits children in reverse order, and each child's
+/// descendants before the child itself.
+fn is_hit_first(path: &[usize], other: &[usize]) -> bool {
+    for (index, other_index) in path.iter().zip(other.iter()) {
+        if index != other_index {
+            return index > other_index;
+        }
+    }
+    path.len() > other.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use accesskit::kurbo::{Affine, Point, Rect, Vec2};
+    use accesskit::{Node, NodeId, Role, Tree, TreeUpdate};
+    use std::{num::NonZeroU128, sync::Arc};
+
+    use crate::tests::NullActionHandler;
+    use crate::{FilterResult, TreeState};
+
+    fn node_id(n: u128) -> NodeId {
+        NodeId(NonZeroU128::new(n).unwrap())
+    }
+
+    // A grid of cells, each containing a nested label, with a few
+    // overlapping, transformed, and hidden nodes thrown in.
+    fn grid_tree() -> crate::Tree {
+        const ROWS: u128 = 8;
+        const COLUMNS: u128 = 8;
+        let mut nodes = Vec::new();
+        let mut root_children = Vec::new();
+        for row in 0..ROWS {
+            for column in 0..COLUMNS {
+                let cell_id = node_id(100 + row * COLUMNS + column);
+                let label_id = node_id(1000 + row * COLUMNS + column);
+                root_children.push(cell_id);
+                nodes.push((
+                    cell_id,
+                    Arc::new(Node {
+                        role: Role::Cell,
+                        transform: (row % 3 == 1).then(|| {
+                            Box::new(Affine::translate(Vec2::new(
+                                (column * 5) as f64,
+                                (row * 3) as f64,
+                            )))
+                        }),
+                        bounds: Some(Rect::new(
+                            (column * 20) as f64,
+                            (row * 20) as f64,
+                            (column * 20 + 25) as f64,
+                            (row * 20 + 25) as f64,
+                        )),
+                        children: vec![label_id],
+                        hidden: row == 2 && column == 2,
+                        ..Default::default()
+                    }),
+                ));
+                nodes.push((
+                    label_id,
+                    Arc::new(Node {
+                        role: Role::StaticText,
+                        transform: (column % 4 == 3)
+                            .then(|| Box::new(Affine::scale(1.5) * Affine::rotate(0.3))),
+                        bounds: Some(Rect::new(
+                            (column * 20 + 2) as f64,
+                            (row * 20 + 2) as f64,
+                            (column * 20 + 12) as f64,
+                            (row * 20 + 12) as f64,
+                        )),
+                        ..Default::default()
+                    }),
+                ));
+            }
+        }
+        nodes.push((
+            node_id(1),
+            Arc::new(Node {
+                role: Role::Window,
+                transform: Some(Box::new(Affine::scale(1.25))),
+                children: root_children,
+                ..Default::default()
+            }),
+        ));
+        let update = TreeUpdate {
+            nodes,
+            tree: Some(Tree::new(node_id(1))),
+            focus: None,
+        };
+        crate::Tree::new(update, Box::new(NullActionHandler {}))
+    }
+
+    fn filter(node: &crate::Node) -> FilterResult {
+        if node.is_hidden() {
+            FilterResult::ExcludeSubtree
+        } else if node.role() == Role::Cell {
+            FilterResult::ExcludeNode
+        } else {
+            FilterResult::Include
+        }
+    }
+
+    fn include_all(_: &crate::Node) -> FilterResult {
+        FilterResult::Include
+    }
+
+    fn assert_same_results(state: &TreeState) {
+        assert!(state.has_spatial_index());
+        let mut hit_count = 0;
+        let starts = [node_id(1), node_id(100 + 9), node_id(100 + 30)];
+        for start in starts {
+            let node = match state.node_by_id(start) {
+                Some(node) => node,
+                None => continue,
+            };
+            let index = state.spatial_index.as_ref().unwrap();
+            for x in 0..50 {
+                for y in 0..50 {
+                    let point = Point::new(x as f64 * 4.1, y as f64 * 4.1);
+                    for f in [
+                        &filter as &dyn Fn(&crate::Node) -> FilterResult,
+                        &include_all,
+                    ] {
+                        let expected = node
+                            .hit_test_exhaustive(point, &f)
+                            .map(|(node, point)| (node.id(), point));
+                        let actual = index
+                            .hit_test(&node, point, &f)
+                            .map(|(node, point)| (node.id(), point));
+                        assert_eq!(expected, actual, "start {:?} at {:?}", start, point);
+                        if actual.is_some() {
+                            hit_count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert!(hit_count > 0);
+    }
+
+    #[test]
+    fn same_results_as_exhaustive_search() {
+        let tree = grid_tree();
+        tree.set_spatial_index_enabled(true);
+        assert_same_results(&tree.read());
+    }
+
+    #[test]
+    fn index_tracks_updates() {
+        let tree = grid_tree();
+        tree.set_spatial_index_enabled(true);
+        let (root, moved_cell, resized_label) = {
+            let state = tree.read();
+            (
+                state.root().data().clone(),
+                state.node_by_id(node_id(100 + 9)).unwrap().data().clone(),
+                state.node_by_id(node_id(1000 + 20)).unwrap().data().clone(),
+            )
+        };
+        // Move one cell under another, transform the root, resize a label,
+        // and remove a cell.
+        let mut root_children = root.children.clone();
+        root_children.retain(|id| *id != node_id(100 + 9) && *id != node_id(100 + 40));
+        let update = TreeUpdate {
+            nodes: vec![
+                (
+                    node_id(1),
+                    Arc::new(Node {
+                        transform: Some(Box::new(Affine::translate(Vec2::new(3.0, -4.0)))),
+                        children: root_children,
+                        ..root
+                    }),
+                ),
+                (
+                    node_id(100 + 30),
+                    Arc::new(Node {
+                        children: vec![node_id(1000 + 30), node_id(100 + 9)],
+                        ..(*tree.read().node_by_id(node_id(100 + 30)).unwrap().data()).clone()
+                    }),
+                ),
+                (
+                    node_id(100 + 9),
+                    Arc::new(Node {
+                        transform: Some(Box::new(Affine::translate(Vec2::new(-10.0, 7.0)))),
+                        ..moved_cell
+                    }),
+                ),
+                (
+                    node_id(1000 + 20),
+                    Arc::new(Node {
+                        bounds: Some(Rect::new(0.0, 0.0, 60.0, 60.0)),
+                        ..resized_label
+                    }),
+                ),
+            ],
+            tree: None,
+            focus: None,
+        };
+        tree.update(update);
+        assert!(tree.read().node_by_id(node_id(100 + 40)).is_none());
+        assert_same_results(&tree.read());
+    }
+
+    #[test]
+    fn index_is_reused_across_updates() {
+        let tree = grid_tree();
+        tree.set_spatial_index_enabled(true);
+        let label = tree
+            .read()
+            .node_by_id(node_id(1000 + 20))
+            .unwrap()
+            .data()
+            .clone();
+        let mut snapshot = None;
+        for i in 1..=3 {
+            let size = 10.0 * i as f64;
+            tree.update(TreeUpdate {
+                nodes: vec![(
+                    node_id(1000 + 20),
+                    Arc::new(Node {
+                        bounds: Some(Rect::new(0.0, 0.0, size, size)),
+                        ..label.clone()
+                    }),
+                )],
+                tree: None,
+                focus: None,
+            });
+            assert_same_results(&tree.read());
+            if i == 1 {
+                snapshot = Some(tree.snapshot());
+            }
+        }
+        // Later updates must not have touched the snapshot's index.
+        assert_same_results(&snapshot.unwrap());
+    }
+
+    #[test]
+    fn disabling_index() {
+        let tree = grid_tree();
+        tree.set_spatial_index_enabled(true);
+        assert!(tree.read().has_spatial_index());
+        tree.set_spatial_index_enabled(false);
+        assert!(!tree.read().has_spatial_index());
+    }
+}
@@ -4,10 +4,19 @@
 // the LICENSE-MIT file), at your option.
 
 use accesskit::kurbo::{Point, Rect};
-use accesskit::{NodeId, Role, TextDirection, TextPosition as WeakPosition};
-use std::{cmp::Ordering, iter::FusedIterator};
-
-use crate::{FilterResult, Node, TreeState};
+use accesskit::{
+    NodeId, Role, TextAnnotation, TextAnnotationKind, TextDecoration, TextDirection,
+    TextPosition as WeakPosition, VerticalOffset,
+};
+use parking_lot::Mutex;
+use std::{
+    cmp::Ordering,
+    iter::FusedIterator,
+    sync::{Arc, Weak},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{FilterResult, Node, NodeState, TreeState};
 
 #[derive(Clone, Copy)]
 pub(crate) struct InnerPosition<'a> {
@@ -18,11 +27,14 @@ pub(crate) struct InnerPosition<'a> {
 impl<'a> InnerPosition<'a> {
     fn upgrade(tree_state: &'a TreeState, weak: WeakPosition) -> Option<Self> {
         let node = tree_state.node_by_id(weak.node)?;
-        if node.role() != Role::InlineTextBox {
+        if node.role() != Role::InlineTextBox
+            && !node.is_embedded_object()
+            && !node.has_synthesized_text()
+        {
             return None;
         }
         let character_index = weak.character_index;
-        if character_index > node.data().character_lengths.len() {
+        if character_index > node.text_box_character_lengths().len() {
             return None;
         }
         Some(Self {
@@ -33,7 +45,7 @@ impl<'a> InnerPosition<'a> {
 
     fn is_word_start(&self) -> bool {
         let mut total_length = 0usize;
-        for length in self.node.data().word_lengths.iter() {
+        for length in self.node.text_box_word_lengths().iter() {
             if total_length == self.character_index {
                 return true;
             }
@@ -46,20 +58,24 @@ impl<'a> InnerPosition<'a> {
         self.character_index == 0
     }
 
-    fn is_line_start(&self) -> bool {
-        self.is_box_start() && self.node.data().previous_on_line.is_none()
+    fn is_line_start(&self, root_node: &Node) -> bool {
+        self.is_box_start()
+            && self.node.data().previous_on_line.is_none()
+            && self.node.line_anchor(root_node).is_none()
     }
 
     fn is_box_end(&self) -> bool {
-        self.character_index == self.node.data().character_lengths.len()
+        self.character_index == self.node.text_box_character_lengths().len()
     }
 
-    fn is_line_end(&self) -> bool {
-        self.is_box_end() && self.node.data().next_on_line.is_none()
+    fn is_line_end(&self, root_node: &Node) -> bool {
+        self.is_box_end()
+            && self.node.data().next_on_line.is_none()
+            && self.node.line_anchor(root_node).is_none()
     }
 
-    fn is_paragraph_end(&self) -> bool {
-        self.is_line_end() && self.node.value().unwrap().ends_with('\n')
+    fn is_paragraph_end(&self, root_node: &Node) -> bool {
+        self.is_line_end(root_node) && self.node.text_box_value().ends_with('\n')
     }
 
     fn is_document_start(&self, root_node: &Node) -> bool {
@@ -97,7 +113,7 @@ impl<'a> InnerPosition<'a> {
             if let Some(node) = self.node.preceding_inline_text_boxes(root_node).next() {
                 return Self {
                     node,
-                    character_index: node.data().character_lengths.len(),
+                    character_index: node.text_box_character_lengths().len(),
                 };
             }
         }
@@ -114,7 +130,7 @@ impl<'a> InnerPosition<'a> {
 
     fn previous_word_start(&self) -> Self {
         let mut total_length_before = 0usize;
-        for length in self.node.data().word_lengths.iter() {
+        for length in self.node.text_box_word_lengths().iter() {
             let new_total_length = total_length_before + (*length as usize);
             if new_total_length >= self.character_index {
                 break;
@@ -129,7 +145,7 @@ impl<'a> InnerPosition<'a> {
 
     fn word_end(&self) -> Self {
         let mut total_length = 0usize;
-        for length in self.node.data().word_lengths.iter() {
+        for length in self.node.text_box_word_lengths().iter() {
             total_length += *length as usize;
             if total_length > self.character_index {
                 break;
@@ -141,8 +157,8 @@ impl<'a> InnerPosition<'a> {
         }
     }
 
-    fn line_start(&self) -> Self {
-        let mut node = self.node;
+    fn line_start(&self, root_node: &Node) -> Self {
+        let mut node = self.node.line_anchor(root_node).unwrap_or(self.node);
         while let Some(id) = node.data().previous_on_line {
             node = node.tree_state.node_by_id(id).unwrap();
         }
@@ -152,14 +168,14 @@ impl<'a> InnerPosition<'a> {
         }
     }
 
-    fn line_end(&self) -> Self {
-        let mut node = self.node;
+    fn line_end(&self, root_node: &Node) -> Self {
+        let mut node = self.node.line_anchor(root_node).unwrap_or(self.node);
         while let Some(id) = node.data().next_on_line {
             node = node.tree_state.node_by_id(id).unwrap();
         }
         Self {
             node,
-            character_index: node.data().character_lengths.len(),
+            character_index: node.text_box_character_lengths().len(),
         }
     }
 
@@ -187,8 +203,13 @@ pub struct Position<'a> {
 
 impl<'a> Position<'a> {
     pub fn is_format_start(&self) -> bool {
-        // TODO: support variable text formatting (part of rich text)
-        self.is_document_start()
+        if self.is_document_start() {
+            return true;
+        }
+        let pos = self.inner.biased_to_start(&self.root_node);
+        let previous = pos.node.preceding_inline_text_boxes(&self.root_node).next();
+        pos.is_box_start()
+            && !matches!(previous, Some(node) if node.text_format() == pos.node.text_format())
     }
 
     pub fn is_word_start(&self) -> bool {
@@ -196,21 +217,24 @@ impl<'a> Position<'a> {
     }
 
     pub fn is_line_start(&self) -> bool {
-        self.inner.is_line_start()
+        self.inner.is_line_start(&self.root_node)
     }
 
     pub fn is_line_end(&self) -> bool {
-        self.inner.is_line_end()
+        self.inner.is_line_end(&self.root_node)
     }
 
     pub fn is_paragraph_start(&self) -> bool {
         self.is_document_start()
             || (self.is_line_start()
-                && self.inner.biased_to_end(&self.root_node).is_paragraph_end())
+                && self
+                    .inner
+                    .biased_to_end(&self.root_node)
+                    .is_paragraph_end(&self.root_node))
     }
 
     pub fn is_paragraph_end(&self) -> bool {
-        self.is_document_end() || self.inner.is_paragraph_end()
+        self.is_document_end() || self.inner.is_paragraph_end(&self.root_node)
     }
 
     pub fn is_page_start(&self) -> bool {
@@ -225,32 +249,89 @@ impl<'a> Position<'a> {
         self.inner.is_document_end(&self.root_node)
     }
 
+    /// Returns the annotations that cover the character after
+    /// this position.
+    pub fn annotations(&self) -> Vec<Annotation<'a>> {
+        let comparable = self.inner.comparable(&self.root_node);
+        self.root_node
+            .text_annotations()
+            .filter(|annotation| {
+                annotation.range.start.comparable(&self.root_node) <= comparable
+                    && comparable < annotation.range.end.comparable(&self.root_node)
+            })
+            .collect()
+    }
+
+    /// Returns the embedded object whose placeholder follows this position.
+    pub fn embedded_object(&self) -> Option<Node<'a>> {
+        let inner = self.inner.biased_to_start(&self.root_node);
+        (inner.node.role() != Role::InlineTextBox
+            && !inner.node.has_synthesized_text()
+            && !inner.is_box_end())
+        .then_some(inner.node)
+    }
+
+    /// Returns the link that contains the character after this position,
+    /// if any. If links are nested, this is the outermost one.
+    pub fn hyperlink(&self) -> Option<Node<'a>> {
+        if self.is_document_end() {
+            return None;
+        }
+        let inner = self.inner.biased_to_start(&self.root_node);
+        enclosing_link(&self.root_node, inner.node)
+    }
+
     pub fn to_degenerate_range(&self) -> Range<'a> {
         Range::new(self.root_node, self.inner, self.inner)
     }
 
-    pub fn to_global_utf16_index(&self) -> usize {
+    fn global_index(&self, length: fn(&str) -> usize) -> usize {
         let mut total_length = 0usize;
         for node in self.root_node.inline_text_boxes() {
-            let node_text = node.value().unwrap();
+            let node_text = node.text_box_value();
             if node.id() == self.inner.node.id() {
-                let character_lengths = &node.data().character_lengths;
+                let character_lengths = node.text_box_character_lengths();
                 let slice_end = character_lengths[..self.inner.character_index]
                     .iter()
                     .copied()
                     .map(usize::from)
                     .sum::<usize>();
-                return total_length
-                    + node_text[..slice_end]
-                        .chars()
-                        .map(char::len_utf16)
-                        .sum::<usize>();
+                return total_length + length(&node_text[..slice_end]);
+            }
+            total_length += length(node_text);
+        }
+        panic!("invalid position")
+    }
+
+    /// Returns the number of characters before this position,
+    /// where a character is whatever the provider treats as one,
+    /// usually a grapheme cluster.
+    pub fn to_global_character_index(&self) -> usize {
+        let mut total_length = 0usize;
+        for node in self.root_node.inline_text_boxes() {
+            if node.id() == self.inner.node.id() {
+                return total_length + self.inner.character_index;
             }
-            total_length += node_text.chars().map(char::len_utf16).sum::<usize>();
+            total_length += node.text_box_character_lengths().len();
         }
         panic!("invalid position")
     }
 
+    /// Returns the number of Unicode code points before this position.
+    pub fn to_global_code_point_index(&self) -> usize {
+        self.global_index(code_point_length)
+    }
+
+    /// Returns the number of bytes before this position when the text
+    /// is encoded as UTF-8.
+    pub fn to_global_byte_index(&self) -> usize {
+        self.global_index(str::len)
+    }
+
+    pub fn to_global_utf16_index(&self) -> usize {
+        self.global_index(utf16_length)
+    }
+
     pub fn to_line_index(&self) -> usize {
         let mut pos = *self;
         if !pos.is_line_start() {
@@ -264,6 +345,19 @@ impl<'a> Position<'a> {
         lines_before_current
     }
 
+    /// Returns the line index and the number of characters between
+    /// the start of the line and this position. A position at the end
+    /// of a wrapped line is on that line, not the next one.
+    pub fn to_line_and_column(&self) -> (usize, usize) {
+        let line_start = if self.is_line_start() {
+            *self
+        } else {
+            self.backward_to_line_start()
+        };
+        let column = self.to_global_character_index() - line_start.to_global_character_index();
+        (self.to_line_index(), column)
+    }
+
     pub fn forward_to_character_start(&self) -> Self {
         let pos = self.inner.biased_to_start(&self.root_node);
         Self {
@@ -300,18 +394,50 @@ impl<'a> Position<'a> {
     }
 
     pub fn forward_to_format_start(&self) -> Self {
-        // TODO: support variable text formatting (part of rich text)
-        self.document_end()
+        Self {
+            root_node: self.root_node,
+            inner: self
+                .forward_to_format_end()
+                .inner
+                .biased_to_start(&self.root_node),
+        }
     }
 
     pub fn forward_to_format_end(&self) -> Self {
-        // TODO: support variable text formatting (part of rich text)
-        self.document_end()
+        let pos = self.inner.biased_to_start(&self.root_node);
+        let format = pos.node.text_format();
+        let node = pos
+            .node
+            .following_inline_text_boxes(&self.root_node)
+            .take_while(|node| node.text_format() == format)
+            .last()
+            .unwrap_or(pos.node);
+        Self {
+            root_node: self.root_node,
+            inner: InnerPosition {
+                node,
+                character_index: node.text_box_character_lengths().len(),
+            },
+        }
     }
 
     pub fn backward_to_format_start(&self) -> Self {
-        // TODO: support variable text formatting (part of rich text)
-        self.document_start()
+        let pos = self.inner.biased_to_end(&self.root_node);
+        let format = pos.node.text_format();
+        let node = pos
+            .node
+            .preceding_inline_text_boxes(&self.root_node)
+            .take_while(|node| node.text_format() == format)
+            .last()
+            .unwrap_or(pos.node);
+        Self {
+            root_node: self.root_node,
+            inner: InnerPosition {
+                node,
+                character_index: 0,
+            }
+            .biased_to_start(&self.root_node),
+        }
     }
 
     pub fn forward_to_word_start(&self) -> Self {
@@ -342,7 +468,9 @@ impl<'a> Position<'a> {
         let pos = self.inner.biased_to_start(&self.root_node);
         Self {
             root_node: self.root_node,
-            inner: pos.line_end().biased_to_start(&self.root_node),
+            inner: pos
+                .line_end(&self.root_node)
+                .biased_to_start(&self.root_node),
         }
     }
 
@@ -350,7 +478,7 @@ impl<'a> Position<'a> {
         let pos = self.inner.biased_to_start(&self.root_node);
         Self {
             root_node: self.root_node,
-            inner: pos.line_end(),
+            inner: pos.line_end(&self.root_node),
         }
     }
 
@@ -358,7 +486,9 @@ impl<'a> Position<'a> {
         let pos = self.inner.biased_to_end(&self.root_node);
         Self {
             root_node: self.root_node,
-            inner: pos.line_start().biased_to_start(&self.root_node),
+            inner: pos
+                .line_start(&self.root_node)
+                .biased_to_start(&self.root_node),
         }
     }
 
@@ -370,7 +500,7 @@ impl<'a> Position<'a> {
                 || current
                     .inner
                     .biased_to_end(&self.root_node)
-                    .is_paragraph_end()
+                    .is_paragraph_end(&self.root_node)
             {
                 break;
             }
@@ -382,7 +512,7 @@ impl<'a> Position<'a> {
         let mut current = *self;
         loop {
             current = current.forward_to_line_end();
-            if current.is_document_end() || current.inner.is_paragraph_end() {
+            if current.is_document_end() || current.inner.is_paragraph_end(&self.root_node) {
                 break;
             }
         }
@@ -419,6 +549,72 @@ impl<'a> Position<'a> {
         }
     }
 
+    /// Returns the unit of text that contains the character after
+    /// this position. At the end of the document, where there's no
+    /// character after it, this is the last unit, unless the position
+    /// is also the start of a unit, as it always is for characters,
+    /// in which case the range is degenerate.
+    ///
+    /// Sentences are found with the Unicode sentence boundary rules,
+    /// since providers don't supply them.
+    pub fn enclosing_unit(&self, unit: Unit) -> Range<'a> {
+        if unit == Unit::Sentence {
+            return self.enclosing_sentence();
+        }
+        let start = match unit {
+            Unit::Format if !self.is_format_start() => self.backward_to_format_start(),
+            Unit::Word if !self.is_word_start() => self.backward_to_word_start(),
+            Unit::Line if !self.is_line_start() => self.backward_to_line_start(),
+            Unit::Paragraph if !self.is_paragraph_start() => self.backward_to_paragraph_start(),
+            Unit::Page if !self.is_page_start() => self.backward_to_page_start(),
+            Unit::Document => self.document_start(),
+            _ => *self,
+        };
+        if start.is_document_end() {
+            return start.to_degenerate_range();
+        }
+        let end = match unit {
+            Unit::Character => start.forward_to_character_end(),
+            Unit::Format => start.forward_to_format_end(),
+            Unit::Word => start.forward_to_word_end(),
+            Unit::Line => start.forward_to_line_end(),
+            Unit::Paragraph => start.forward_to_paragraph_end(),
+            Unit::Page => start.forward_to_page_end(),
+            Unit::Sentence | Unit::Document => start.document_end(),
+        };
+        Range::new(self.root_node, start.inner, end.inner)
+    }
+
+    fn enclosing_sentence(&self) -> Range<'a> {
+        let text = Range::new(
+            self.root_node,
+            self.root_node.document_start(),
+            self.root_node.document_end(),
+        )
+        .text();
+        let index = self.global_index(str::len);
+        let (start, end) = text
+            .split_sentence_bound_indices()
+            .map(|(start, sentence)| (start, start + sentence.len()))
+            .find(|(_, end)| index < *end || *end == text.len())
+            .unwrap_or((0, 0));
+        // If the provider's characters don't follow grapheme cluster
+        // boundaries, a sentence boundary may be in the middle of one,
+        // in which case the character goes with the earlier sentence.
+        let position = |index| {
+            let pos = self
+                .root_node
+                .text_position_from_global_byte_index(index)
+                .unwrap();
+            if pos.global_index(str::len) == index {
+                pos.inner
+            } else {
+                pos.forward_to_character_end().inner
+            }
+        };
+        Range::new(self.root_node, position(start), position(end))
+    }
+
     pub fn document_start(&self) -> Self {
         Self {
             root_node: self.root_node,
@@ -451,6 +647,102 @@ pub enum AttributeValue<T> {
     Mixed,
 }
 
+/// The formatting of a run of text. Like the node accessors that it's
+/// built from, it includes the values inherited from ancestors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Format<'a> {
+    pub font_family: Option<&'a str>,
+    pub font_size: Option<f32>,
+    pub font_weight: Option<f32>,
+    pub bold: bool,
+    pub italic: bool,
+    pub background_color: Option<u32>,
+    pub foreground_color: Option<u32>,
+    pub overline: Option<TextDecoration>,
+    pub strikethrough: Option<TextDecoration>,
+    pub underline: Option<TextDecoration>,
+    pub vertical_offset: Option<VerticalOffset>,
+    pub language: Option<&'a str>,
+    pub is_spelling_error: bool,
+    pub is_grammar_error: bool,
+}
+
+/// A unit of text, as used by [`Position::enclosing_unit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Character,
+    Format,
+    Word,
+    Sentence,
+    Line,
+    Paragraph,
+    Page,
+    Document,
+}
+
+/// An edit to the text of a node that supports text ranges, found
+/// by comparing the node's text before and after an update.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta {
+    /// The number of characters (Unicode scalar values) before the edit,
+    /// which are the same in the old and new text.
+    pub offset: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Delta {
+    /// Returns `None` if the texts are equal. If there's more than one
+    /// way to explain the edit, as there is when a character is typed
+    /// next to an identical one, the edit is placed as late as possible.
+    pub(crate) fn new(old: &str, new: &str) -> Option<Self> {
+        if old == new {
+            return None;
+        }
+        let (prefix_len, offset) = old
+            .char_indices()
+            .zip(new.chars())
+            .take_while(|((_, old), new)| old == new)
+            .fold((0, 0), |(_, count), ((index, c), _)| {
+                (index + c.len_utf8(), count + 1)
+            });
+        let suffix_len = old[prefix_len..]
+            .chars()
+            .rev()
+            .zip(new[prefix_len..].chars().rev())
+            .take_while(|(old, new)| old == new)
+            .map(|(c, _)| c.len_utf8())
+            .sum::<usize>();
+        Some(Self {
+            offset,
+            removed: old[prefix_len..old.len() - suffix_len].into(),
+            inserted: new[prefix_len..new.len() - suffix_len].into(),
+        })
+    }
+}
+
+/// An annotation on the text of a node that supports text ranges.
+#[derive(Clone, Copy)]
+pub struct Annotation<'a> {
+    data: &'a TextAnnotation,
+    range: Range<'a>,
+}
+
+impl<'a> Annotation<'a> {
+    pub fn kind(&self) -> TextAnnotationKind {
+        self.data.kind
+    }
+
+    pub fn range(&self) -> Range<'a> {
+        self.range
+    }
+
+    pub fn details(&self) -> Option<Node<'a>> {
+        let details = self.data.details?;
+        self.range.node.tree_state.node_by_id(details)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Range<'a> {
     pub(crate) node: Node<'a>,
@@ -490,7 +782,7 @@ impl<'a> Range<'a> {
 
     fn walk<F, T>(&self, mut f: F) -> Option<T>
     where
-        F: FnMut(&Node) -> Option<T>,
+        F: FnMut(&Node<'a>) -> Option<T>,
     {
         // If the range is degenerate, we don't want to normalize it.
         // This is important e.g. when getting the bounding rectangle
@@ -522,7 +814,7 @@ impl<'a> Range<'a> {
     pub fn text(&self) -> String {
         let mut result = String::new();
         self.walk::<_, ()>(|node| {
-            let character_lengths = &node.data().character_lengths;
+            let character_lengths = node.text_box_character_lengths();
             let start_index = if node.id() == self.start.node.id() {
                 self.start.character_index
             } else {
@@ -533,7 +825,7 @@ impl<'a> Range<'a> {
             } else {
                 character_lengths.len()
             };
-            let value = node.value().unwrap();
+            let value = node.text_box_value();
             let s = if start_index == end_index {
                 ""
             } else if start_index == 0 && end_index == character_lengths.len() {
@@ -558,6 +850,221 @@ impl<'a> Range<'a> {
         result
     }
 
+    /// Returns the embedded objects whose placeholders are in this range.
+    pub fn embedded_objects(&self) -> Vec<Node<'a>> {
+        let mut result = Vec::new();
+        if self.is_degenerate() {
+            return result;
+        }
+        // Since the range isn't degenerate, it covers all of every
+        // embedded object it walks.
+        self.walk::<_, ()>(|node| {
+            if node.role() != Role::InlineTextBox && !node.has_synthesized_text() {
+                result.push(*node);
+            }
+            None
+        });
+        result
+    }
+
+    /// Returns the deepest node that contains all of this range,
+    /// which may be an inline text box or an embedded object.
+    /// If there's no such node below the range's node, this returns
+    /// the range's node.
+    pub fn enclosing_node(&self) -> Node<'a> {
+        let start = self.start.biased_to_start(&self.node).node;
+        let end = if self.is_degenerate() {
+            start
+        } else {
+            self.end.biased_to_end(&self.node).node
+        };
+        let mut current = Some(start);
+        while let Some(node) = current {
+            if node.id() == self.node.id() || end.is_descendant_of(&node) {
+                return node;
+            }
+            current = node.parent();
+        }
+        self.node
+    }
+
+    /// Returns the first sub-range of this range whose text matches
+    /// `needle`, or the last one if `backward` is true. Matches always
+    /// start and end on character boundaries; with `ignore_case`,
+    /// the text and the needle are compared in lowercase, one character
+    /// at a time.
+    pub fn find_text(&self, needle: &str, backward: bool, ignore_case: bool) -> Option<Range<'a>> {
+        let fold = |s: &str| {
+            if ignore_case {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            }
+        };
+        let needle = fold(needle);
+        if needle.is_empty() {
+            return None;
+        }
+        // The byte offset in `haystack` where each character starts,
+        // along with the positions before and after that character.
+        let mut haystack = String::new();
+        let mut characters = Vec::<(usize, InnerPosition<'a>, InnerPosition<'a>)>::new();
+        self.walk::<_, ()>(|node| {
+            let character_lengths = node.text_box_character_lengths();
+            let start_index = if node.id() == self.start.node.id() {
+                self.start.character_index
+            } else {
+                0
+            };
+            let end_index = if node.id() == self.end.node.id() {
+                self.end.character_index
+            } else {
+                character_lengths.len()
+            };
+            let value = node.text_box_value();
+            let mut offset = character_lengths[..start_index]
+                .iter()
+                .copied()
+                .map(usize::from)
+                .sum::<usize>();
+            for (character_index, length) in character_lengths
+                .iter()
+                .enumerate()
+                .take(end_index)
+                .skip(start_index)
+            {
+                let length = usize::from(*length);
+                characters.push((
+                    haystack.len(),
+                    InnerPosition {
+                        node: *node,
+                        character_index,
+                    },
+                    InnerPosition {
+                        node: *node,
+                        character_index: character_index + 1,
+                    },
+                ));
+                haystack.push_str(&fold(&value[offset..offset + length]));
+                offset += length;
+            }
+            None
+        });
+        let start_of = |offset| {
+            characters
+                .binary_search_by_key(&offset, |(start, _, _)| *start)
+                .ok()
+        };
+        let end_of = |offset| {
+            if offset == haystack.len() {
+                characters.len().checked_sub(1)
+            } else {
+                start_of(offset).and_then(|index| index.checked_sub(1))
+            }
+        };
+        // Try a match at every character start, rather than only at
+        // non-overlapping occurrences, since an occurrence that doesn't
+        // end on a character boundary may overlap a valid match.
+        let to_range = |first: usize| {
+            let offset = characters[first].0;
+            if !haystack[offset..].starts_with(&needle) {
+                return None;
+            }
+            let last = end_of(offset + needle.len())?;
+            Some(Range::new(
+                self.node,
+                characters[first].1,
+                characters[last].2,
+            ))
+        };
+        if backward {
+            (0..characters.len()).rev().find_map(to_range)
+        } else {
+            (0..characters.len()).find_map(to_range)
+        }
+    }
+
+    /// Returns the annotations that overlap this range, or for
+    /// a degenerate range, that cover the character after it.
+    pub fn annotations(&self) -> Vec<Annotation<'a>> {
+        if self.is_degenerate() {
+            return self.start().annotations();
+        }
+        let start = self.start.comparable(&self.node);
+        let end = self.end.comparable(&self.node);
+        self.node
+            .text_annotations()
+            .filter(|annotation| {
+                annotation.range.start.comparable(&self.node) < end
+                    && start < annotation.range.end.comparable(&self.node)
+            })
+            .collect()
+    }
+
+    /// Splits the range into maximal runs of text for which `f` returns
+    /// the same value. A degenerate range is a single run.
+    fn runs<F, T>(&self, f: F) -> Vec<(Range<'a>, T)>
+    where
+        F: Fn(&Node<'a>) -> T,
+        T: PartialEq,
+    {
+        let mut runs = Vec::<(Range<'a>, T)>::new();
+        self.walk::<_, ()>(|node| {
+            let start_index = if node.id() == self.start.node.id() {
+                self.start.character_index
+            } else {
+                0
+            };
+            let end_index = if node.id() == self.end.node.id() {
+                self.end.character_index
+            } else {
+                node.text_box_character_lengths().len()
+            };
+            if start_index == end_index && !(runs.is_empty() && self.is_degenerate()) {
+                return None;
+            }
+            let start = InnerPosition {
+                node: *node,
+                character_index: start_index,
+            };
+            let end = InnerPosition {
+                node: *node,
+                character_index: end_index,
+            };
+            let value = f(node);
+            match runs.last_mut() {
+                Some((range, last_value)) if *last_value == value => range.end = end,
+                _ => runs.push((Range::new(self.node, start, end), value)),
+            }
+            None
+        });
+        runs
+    }
+
+    /// Splits the range into maximal runs of identically formatted text.
+    pub fn format_runs(&self) -> Vec<(Range<'a>, Format<'a>)> {
+        self.runs(Node::text_format)
+    }
+
+    /// Returns the first maximal run of text in this range for which
+    /// `f` returns `value`, or the last one if `backward` is true.
+    pub fn find_attribute<F, T>(&self, f: F, value: &T, backward: bool) -> Option<Range<'a>>
+    where
+        F: Fn(&Format<'a>) -> T,
+        T: PartialEq,
+    {
+        let runs = self.runs(|node| f(&node.text_format()));
+        let mut matches = runs
+            .into_iter()
+            .filter(|(_, run_value)| run_value == value)
+            .map(|(range, _)| range);
+        if backward {
+            matches.last()
+        } else {
+            matches.next()
+        }
+    }
+
     /// Returns the range's transformed bounding boxes relative to the tree's
     /// container (e.g. window).
     ///
@@ -574,6 +1081,35 @@ impl<'a> Range<'a> {
                     return Some(Vec::new());
                 }
             };
+            let character_lengths = node.text_box_character_lengths();
+            let start_index = if node.id() == self.start.node.id() {
+                self.start.character_index
+            } else {
+                0
+            };
+            let end_index = if node.id() == self.end.node.id() {
+                self.end.character_index
+            } else {
+                character_lengths.len()
+            };
+            if node.has_synthesized_text() {
+                // Synthesized text has no character geometry, so we can
+                // only give the node's bounds for the whole text.
+                if start_index == end_index && !character_lengths.is_empty() {
+                    return None;
+                }
+                if start_index != 0 || end_index != character_lengths.len() {
+                    return Some(Vec::new());
+                }
+                result.push(node.transform().transform_rect_bbox(rect));
+                return None;
+            }
+            if node.role() != Role::InlineTextBox {
+                // An embedded object is a single character, so the range
+                // covers all of it.
+                result.push(node.transform().transform_rect_bbox(rect));
+                return None;
+            }
             let positions = match &node.data().character_positions {
                 Some(positions) => positions,
                 None => {
@@ -586,23 +1122,12 @@ impl<'a> Range<'a> {
                     return Some(Vec::new());
                 }
             };
-            let direction = match node.data().text_direction {
+            let direction = match node.text_direction() {
                 Some(direction) => direction,
                 None => {
                     return Some(Vec::new());
                 }
             };
-            let character_lengths = &node.data().character_lengths;
-            let start_index = if node.id() == self.start.node.id() {
-                self.start.character_index
-            } else {
-                0
-            };
-            let end_index = if node.id() == self.end.node.id() {
-                self.end.character_index
-            } else {
-                character_lengths.len()
-            };
             if start_index != 0 || end_index != character_lengths.len() {
                 let pixel_start = if start_index < character_lengths.len() {
                     positions[start_index]
@@ -751,88 +1276,697 @@ impl WeakRange {
     }
 }
 
-fn text_node_filter(root_id: NodeId, node: &Node) -> FilterResult {
-    if node.id() == root_id || node.role() == Role::InlineTextBox {
-        FilterResult::Include
-    } else {
-        FilterResult::ExcludeNode
-    }
+#[derive(Clone, Copy, Debug)]
+struct LiveRangeState {
+    node_id: NodeId,
+    start: usize,
+    end: usize,
+    /// The [generation](TreeState::generation) of the tree state that
+    /// the offsets refer to.
+    generation: u64,
+    /// The offsets before the update that produced `generation`, for
+    /// readers that still hold the tree state from before that update.
+    previous: Option<(usize, usize)>,
 }
 
-fn character_index_at_point(node: &Node, point: Point) -> usize {
-    // We know the node has a bounding rectangle because it was returned
-    // by a hit test.
-    let rect = node.data().bounds.as_ref().unwrap();
-    let character_lengths = &node.data().character_lengths;
-    let positions = match &node.data().character_positions {
-        Some(positions) => positions,
-        None => {
-            return 0;
+impl LiveRangeState {
+    fn new(range: &Range) -> Self {
+        Self {
+            node_id: range.node.id(),
+            start: range.start().to_global_code_point_index(),
+            end: range.end().to_global_code_point_index(),
+            generation: range.node.tree_state.generation,
+            previous: None,
         }
-    };
-    let widths = match &node.data().character_widths {
-        Some(widths) => widths,
-        None => {
-            return 0;
+    }
+
+    fn apply_delta(&mut self, delta: &Delta) {
+        let edit_start = delta.offset;
+        let edit_end = edit_start + delta.removed.chars().count();
+        let inserted = delta.inserted.chars().count();
+        let adjust = |offset: usize| {
+            if offset >= edit_end {
+                offset - (edit_end - edit_start) + inserted
+            } else {
+                edit_start
+            }
+        };
+        if self.start >= edit_start {
+            self.start = adjust(self.start);
         }
-    };
-    let direction = match node.data().text_direction {
-        Some(direction) => direction,
-        None => {
-            return 0;
+        if self.end > edit_start {
+            self.end = adjust(self.end);
         }
-    };
-    for (i, (position, width)) in positions.iter().zip(widths.iter()).enumerate().rev() {
-        let relative_pos = match direction {
-            TextDirection::LeftToRight => point.x - rect.x0,
-            TextDirection::RightToLeft => rect.x1 - point.x,
-            // Note: The following directions assume that the rectangle,
-            // in the node's coordinate space, is y-down. TBD: Will we
-            // ever encounter a case where this isn't true?
-            TextDirection::TopToBottom => point.y - rect.y0,
-            TextDirection::BottomToTop => rect.y1 - point.y,
-        };
-        if relative_pos >= f64::from(*position) && relative_pos < f64::from(*position + *width) {
-            return i;
+        // A degenerate range at the edit follows the inserted text.
+        self.end = self.end.max(self.start);
+    }
+
+    /// Brings offsets from the generation before the latest update
+    /// up to date. Offsets that are even older can't be adjusted,
+    /// since only the latest update's deltas are kept.
+    fn catch_up(&mut self, latest: &LatestUpdate) {
+        if self.generation >= latest.generation {
+            return;
+        }
+        if self.generation + 1 == latest.generation {
+            self.previous = Some((self.start, self.end));
+            if let Some((_, delta)) = latest.deltas.iter().find(|(id, _)| *id == self.node_id) {
+                self.apply_delta(delta);
+            }
+        } else {
+            self.previous = None;
+        }
+        self.generation = latest.generation;
+    }
+
+    fn offsets_in(&self, tree_state: &TreeState) -> (usize, usize) {
+        match self.previous {
+            Some(previous) if tree_state.generation + 1 == self.generation => previous,
+            _ => (self.start, self.end),
         }
     }
-    character_lengths.len()
 }
 
-impl<'a> Node<'a> {
-    fn inline_text_boxes(
-        &self,
-    ) -> impl DoubleEndedIterator<Item = Node<'a>> + FusedIterator<Item = Node<'a>> + 'a {
-        let id = self.id();
-        self.filtered_children(move |node| text_node_filter(id, node))
+#[derive(Default)]
+struct LatestUpdate {
+    generation: u64,
+    deltas: Vec<(NodeId, Delta)>,
+}
+
+#[derive(Default)]
+struct LiveRangeSetInner {
+    ranges: Vec<Weak<Mutex<LiveRangeState>>>,
+    latest: LatestUpdate,
+}
+
+/// The live ranges that a tree adjusts as it's updated.
+///
+/// Each range records the generation of the tree state its offsets refer
+/// to, so a range that was created from a state older than the latest
+/// update is adjusted exactly once, whether it was tracked before or after
+/// the update's deltas were applied.
+#[derive(Clone, Default)]
+pub(crate) struct LiveRangeSet(Arc<Mutex<LiveRangeSetInner>>);
+
+impl LiveRangeSet {
+    pub(crate) fn track(&self, range: &Range) -> LiveRange {
+        self.track_state(LiveRangeState::new(range))
     }
 
-    fn following_inline_text_boxes(
-        &self,
-        root_node: &Node,
-    ) -> impl DoubleEndedIterator<Item = Node<'a>> + FusedIterator<Item = Node<'a>> + 'a {
-        let id = root_node.id();
-        self.following_filtered_siblings(move |node| text_node_filter(id, node))
+    fn track_state(&self, mut state: LiveRangeState) -> LiveRange {
+        let mut inner = self.0.lock();
+        state.catch_up(&inner.latest);
+        let state = Arc::new(Mutex::new(state));
+        inner.ranges.push(Arc::downgrade(&state));
+        LiveRange {
+            state,
+            set: self.clone(),
+        }
     }
 
-    fn preceding_inline_text_boxes(
-        &self,
-        root_node: &Node,
-    ) -> impl DoubleEndedIterator<Item = Node<'a>> + FusedIterator<Item = Node<'a>> + 'a {
-        let id = root_node.id();
-        self.preceding_filtered_siblings(move |node| text_node_filter(id, node))
+    fn set_state(&self, target: &Mutex<LiveRangeState>, mut state: LiveRangeState) {
+        let inner = self.0.lock();
+        state.catch_up(&inner.latest);
+        *target.lock() = state;
     }
 
-    pub fn supports_text_ranges(&self) -> bool {
-        matches!(
-            self.role(),
-            Role::StaticText | Role::TextField | Role::Document | Role::SpinButton
-        ) && self.inline_text_boxes().next().is_some()
+    pub(crate) fn is_empty(&self) -> bool {
+        let mut inner = self.0.lock();
+        inner.ranges.retain(|range| range.strong_count() > 0);
+        inner.ranges.is_empty()
     }
 
-    fn document_start(&self) -> InnerPosition<'a> {
-        let node = self.inline_text_boxes().next().unwrap();
-        InnerPosition {
+    /// Applies each document's text delta from the update that produced
+    /// the given generation of the tree state to the ranges in that
+    /// document, forgetting the ranges that have been dropped. This must
+    /// be called before that state is published.
+    pub(crate) fn apply_deltas(&self, generation: u64, deltas: Vec<(NodeId, Delta)>) {
+        let mut inner = self.0.lock();
+        inner.latest = LatestUpdate { generation, deltas };
+        let inner = &mut *inner;
+        let latest = &inner.latest;
+        inner.ranges.retain(|range| match range.upgrade() {
+            Some(range) => {
+                range.lock().catch_up(latest);
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+/// A text range that keeps covering the same text as the document
+/// is edited, unlike a [`WeakRange`], which refers to specific
+/// inline text boxes.
+///
+/// The endpoints are stored as offsets in Unicode scalar values,
+/// which the tree adjusts by the document's [`Delta`] whenever an update
+/// changes its text. An endpoint after the edited text shifts by
+/// the change in length, and an endpoint within removed text moves to
+/// the start of the edit, so a range whose text is removed entirely
+/// collapses there. Text inserted at either endpoint of a nondegenerate
+/// range is left outside it, while a degenerate range moves past
+/// the inserted text, as a caret would.
+///
+/// Created by [`Tree::track_text_range`](crate::Tree::track_text_range).
+/// The tree stops tracking a range once it's dropped, and a clone
+/// is tracked separately from the original.
+pub struct LiveRange {
+    state: Arc<Mutex<LiveRangeState>>,
+    set: LiveRangeSet,
+}
+
+impl LiveRange {
+    pub fn node_id(&self) -> NodeId {
+        self.state.lock().node_id
+    }
+
+    /// Returns the offsets of the start and end of the range,
+    /// in Unicode scalar values. These can be compared without
+    /// upgrading the range.
+    pub fn offsets(&self) -> (usize, usize) {
+        let state = self.state.lock();
+        (state.start, state.end)
+    }
+
+    pub fn upgrade_node<'a>(&self, tree_state: &'a TreeState) -> Option<Node<'a>> {
+        tree_state
+            .node_by_id(self.node_id())
+            .filter(Node::supports_text_ranges)
+    }
+
+    /// The tree state may also be the one from just before the latest
+    /// update. Offsets past the end of the document, e.g. because the
+    /// state is even older, are clamped to the end of the document.
+    pub fn upgrade<'a>(&self, tree_state: &'a TreeState) -> Option<Range<'a>> {
+        let state = *self.state.lock();
+        let node = tree_state
+            .node_by_id(state.node_id)
+            .filter(Node::supports_text_ranges)?;
+        let position = |index| {
+            node.text_position_from_global_code_point_index(index)
+                .map_or_else(|| node.document_end(), |pos| pos.inner)
+        };
+        let (start, end) = state.offsets_in(tree_state);
+        Some(Range::new(node, position(start), position(end)))
+    }
+
+    /// Moves the range to the text covered by `range`, which may be
+    /// in a different document.
+    pub fn set(&self, range: &Range) {
+        self.set.set_state(&self.state, LiveRangeState::new(range));
+    }
+}
+
+impl Clone for LiveRange {
+    fn clone(&self) -> Self {
+        let state = *self.state.lock();
+        self.set.track_state(state)
+    }
+}
+
+impl PartialEq for LiveRange {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_id() == other.node_id() && self.offsets() == other.offsets()
+    }
+}
+
+impl Eq for LiveRange {}
+
+fn code_point_length(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn utf16_length(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+/// The character that stands for an embedded object in the text
+/// of a document.
+const OBJECT_REPLACEMENT_CHARACTER: &str = "\u{fffc}";
+const OBJECT_REPLACEMENT_CHARACTER_LENGTH: u8 = OBJECT_REPLACEMENT_CHARACTER.len() as u8;
+
+/// Returns true if the node would be an embedded object if it were
+/// in a document: a leaf that isn't text, or a generic container
+/// that means nothing without its children.
+fn is_embedded_object_candidate(node: &Node) -> bool {
+    node.data().children.is_empty()
+        && !matches!(
+            node.role(),
+            Role::InlineTextBox
+                | Role::StaticText
+                | Role::LineBreak
+                | Role::Unknown
+                | Role::GenericContainer
+                | Role::Presentation
+        )
+}
+
+/// Returns true if a node with this role, but no inline text boxes,
+/// should have a text model synthesized from its value or name.
+fn has_synthesized_text_role(role: Role) -> bool {
+    matches!(
+        role,
+        Role::StaticText
+            | Role::LabelText
+            | Role::Heading
+            | Role::Paragraph
+            | Role::ListItem
+            | Role::ListMarker
+            | Role::Link
+            | Role::Caption
+            | Role::FigureCaption
+            | Role::Legend
+            | Role::Tooltip
+            | Role::TextField
+            | Role::SpinButton
+    )
+}
+
+/// The text model of a node that has no inline text boxes, such as a
+/// simple label. The node's whole value or name is a single line,
+/// and the node itself plays the part of its only inline text box,
+/// without character geometry.
+#[derive(PartialEq)]
+pub(crate) struct SynthesizedText {
+    value: String,
+    character_lengths: Box<[u8]>,
+    word_lengths: Box<[u8]>,
+}
+
+impl SynthesizedText {
+    fn new(value: String) -> Self {
+        let mut character_lengths = Vec::new();
+        for grapheme in value.graphemes(true) {
+            // A character's length must fit in a byte, so split any
+            // longer grapheme cluster at code point boundaries.
+            let mut length = 0usize;
+            for c in grapheme.chars() {
+                if length + c.len_utf8() > usize::from(u8::MAX) {
+                    character_lengths.push(length as u8);
+                    length = 0;
+                }
+                length += c.len_utf8();
+            }
+            character_lengths.push(length as u8);
+        }
+
+        // As with the word lengths that providers usually give us,
+        // a word includes any spaces or punctuation that follow it.
+        let mut word_starts = Vec::new();
+        let mut seen_word = false;
+        for (index, segment) in value.split_word_bound_indices() {
+            let is_word = segment.chars().any(char::is_alphanumeric);
+            if is_word && seen_word {
+                word_starts.push(index);
+            }
+            seen_word |= is_word;
+        }
+        let mut word_starts = word_starts.into_iter().peekable();
+        let mut word_lengths = Vec::new();
+        let mut offset = 0usize;
+        let mut length = 0u8;
+        for character_length in &character_lengths {
+            if word_starts.next_if(|start| *start <= offset).is_some() || length == u8::MAX {
+                word_lengths.push(length);
+                length = 0;
+            }
+            length += 1;
+            offset += usize::from(*character_length);
+        }
+        word_lengths.push(length);
+
+        Self {
+            value,
+            character_lengths: character_lengths.into(),
+            word_lengths: word_lengths.into(),
+        }
+    }
+}
+
+fn text_node_filter(root_id: NodeId, root_role: Role, node: &Node) -> FilterResult {
+    if node.id() == root_id
+        || node.role() == Role::InlineTextBox
+        || (root_role == Role::Document
+            && (is_embedded_object_candidate(node) || node.has_synthesized_text())
+            && is_in_text_of(node, root_id))
+    {
+        FilterResult::Include
+    } else {
+        FilterResult::ExcludeNode
+    }
+}
+
+/// Returns true if no node between `node` and its ancestor `root_id`
+/// supports text ranges, so that `node` belongs to that ancestor's text
+/// rather than a nested one. The walk stops before `root_id` itself,
+/// which may be finding its own inline text boxes.
+fn is_in_text_of(node: &Node, root_id: NodeId) -> bool {
+    let mut current = node.parent();
+    while let Some(ancestor) = current {
+        if ancestor.id() == root_id {
+            return true;
+        }
+        if ancestor.supports_text_ranges() {
+            return false;
+        }
+        current = ancestor.parent();
+    }
+    false
+}
+
+/// Returns the outermost link that contains `node`, within `root_node`.
+fn enclosing_link<'a>(root_node: &Node, node: Node<'a>) -> Option<Node<'a>> {
+    let mut result = None;
+    let mut current = Some(node);
+    while let Some(node) = current {
+        if node.id() == root_node.id() {
+            break;
+        }
+        if node.role() == Role::Link {
+            result = Some(node);
+        }
+        current = node.parent();
+    }
+    result
+}
+
+fn is_vertical(direction: TextDirection) -> bool {
+    matches!(
+        direction,
+        TextDirection::TopToBottom | TextDirection::BottomToTop
+    )
+}
+
+/// Returns the distance of `point` from the start edge of `rect`, measured
+/// along the inline axis in the given text direction. The result is negative
+/// if the point is before the start edge.
+fn inline_offset(rect: &Rect, direction: TextDirection, point: Point) -> f64 {
+    match direction {
+        TextDirection::LeftToRight => point.x - rect.x0,
+        TextDirection::RightToLeft => rect.x1 - point.x,
+        // Note: The following directions assume that the rectangle,
+        // in the node's coordinate space, is y-down. TBD: Will we
+        // ever encounter a case where this isn't true?
+        TextDirection::TopToBottom => point.y - rect.y0,
+        TextDirection::BottomToTop => rect.y1 - point.y,
+    }
+}
+
+fn distance_outside(value: f64, start: f64, end: f64) -> f64 {
+    if value < start {
+        start - value
+    } else if value > end {
+        value - end
+    } else {
+        0.0
+    }
+}
+
+/// Returns the index of the character at `point`, which is in the node's
+/// coordinate space. If the point is before the start of the box along
+/// its inline axis, the result is 0; if it's past the end, the result
+/// is the number of characters.
+fn character_index_at_point(node: &Node, point: Point) -> usize {
+    // We know the node has a bounding rectangle because it was returned
+    // by a hit test or chosen as the nearest box.
+    let rect = node.data().bounds.as_ref().unwrap();
+    let character_lengths = node.text_box_character_lengths();
+    let direction = node.text_direction().unwrap_or(TextDirection::LeftToRight);
+    let relative_pos = inline_offset(rect, direction, point);
+    let (positions, widths) = match (
+        &node.data().character_positions,
+        &node.data().character_widths,
+    ) {
+        (Some(positions), Some(widths)) => (positions, widths),
+        _ => {
+            let extent = if is_vertical(direction) {
+                rect.height()
+            } else {
+                rect.width()
+            };
+            return if relative_pos < extent {
+                0
+            } else {
+                character_lengths.len()
+            };
+        }
+    };
+    if let (Some(position), Some(width)) = (positions.last(), widths.last()) {
+        if relative_pos >= f64::from(*position + *width) {
+            return character_lengths.len();
+        }
+    }
+    // Adjacent characters may overlap slightly, in which case the later
+    // character wins. A point in a gap between characters belongs to
+    // the preceding one.
+    positions
+        .iter()
+        .rposition(|position| relative_pos >= f64::from(*position))
+        .unwrap_or(0)
+}
+
+impl<'a> Node<'a> {
+    fn inline_text_boxes(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = Node<'a>> + FusedIterator<Item = Node<'a>> + 'a {
+        let id = self.id();
+        let role = self.role();
+        let synthesized = self.has_synthesized_text().then_some(*self);
+        self.filtered_children(move |node| text_node_filter(id, role, node))
+            .chain(synthesized)
+    }
+
+    fn following_inline_text_boxes(
+        &self,
+        root_node: &Node,
+    ) -> impl DoubleEndedIterator<Item = Node<'a>> + FusedIterator<Item = Node<'a>> + 'a {
+        let id = root_node.id();
+        let role = root_node.role();
+        // A node with synthesized text is its own only box.
+        let is_root = self.id() == id;
+        self.following_filtered_siblings(move |node| {
+            if is_root {
+                FilterResult::ExcludeSubtree
+            } else {
+                text_node_filter(id, role, node)
+            }
+        })
+    }
+
+    fn preceding_inline_text_boxes(
+        &self,
+        root_node: &Node,
+    ) -> impl DoubleEndedIterator<Item = Node<'a>> + FusedIterator<Item = Node<'a>> + 'a {
+        let id = root_node.id();
+        let role = root_node.role();
+        let is_root = self.id() == id;
+        self.preceding_filtered_siblings(move |node| {
+            if is_root {
+                FilterResult::ExcludeSubtree
+            } else {
+                text_node_filter(id, role, node)
+            }
+        })
+    }
+
+    /// Returns the text model synthesized from this node's value or name,
+    /// or `None` if it has children or its role has no text. Such a node
+    /// is its own only box, and is also a box in the text of an enclosing
+    /// [`Role::Document`].
+    pub(crate) fn synthesized_text(&self) -> Option<&'a SynthesizedText> {
+        let state: &'a NodeState = self.state;
+        state
+            .cache
+            .synthesized_text
+            .get_or_init(|| {
+                if !has_synthesized_text_role(self.role()) || !self.data().children.is_empty() {
+                    return None;
+                }
+                let value = match self.value() {
+                    Some(value) => value.to_string(),
+                    None if self.is_text_field() || self.role() == Role::SpinButton => {
+                        String::new()
+                    }
+                    None => self.name()?,
+                };
+                Some(SynthesizedText::new(value))
+            })
+            .as_ref()
+    }
+
+    /// Returns true if this node's text model is synthesized from its
+    /// value or name, because it has no inline text boxes.
+    pub(crate) fn has_synthesized_text(&self) -> bool {
+        self.synthesized_text().is_some()
+    }
+
+    /// Returns true if this node is an embedded object that isn't linked
+    /// to the boxes on its line with `previous_on_line` and `next_on_line`,
+    /// as is the case for most providers.
+    fn is_unlinked_object(&self) -> bool {
+        self.role() != Role::InlineTextBox
+            && !self.has_synthesized_text()
+            && self.data().previous_on_line.is_none()
+            && self.data().next_on_line.is_none()
+    }
+
+    /// Returns the box whose line this unlinked embedded object is in
+    /// the middle of, if any. That's the nearest linked box before it,
+    /// if that box's line continues after the object; otherwise the object
+    /// is on a line of its own.
+    fn line_anchor(&self, root_node: &Node) -> Option<Node<'a>> {
+        if !self.is_unlinked_object() {
+            return None;
+        }
+        let anchor = self
+            .preceding_inline_text_boxes(root_node)
+            .find(|node| !node.is_unlinked_object())?;
+        anchor.data().next_on_line.is_some().then_some(anchor)
+    }
+
+    /// Returns the text of an inline text box, the synthesized text
+    /// of a node without boxes, or the placeholder for an embedded object.
+    fn text_box_value(&self) -> &str {
+        if self.role() == Role::InlineTextBox {
+            self.value().unwrap()
+        } else if let Some(text) = self.synthesized_text() {
+            &text.value
+        } else {
+            OBJECT_REPLACEMENT_CHARACTER
+        }
+    }
+
+    fn text_box_character_lengths(&self) -> &[u8] {
+        if self.role() == Role::InlineTextBox {
+            &self.data().character_lengths
+        } else if let Some(text) = self.synthesized_text() {
+            &text.character_lengths
+        } else {
+            &[OBJECT_REPLACEMENT_CHARACTER_LENGTH]
+        }
+    }
+
+    fn text_box_word_lengths(&self) -> &[u8] {
+        if self.role() == Role::InlineTextBox {
+            &self.data().word_lengths
+        } else if let Some(text) = self.synthesized_text() {
+            &text.word_lengths
+        } else {
+            &[1]
+        }
+    }
+
+    /// Returns true if this node is represented in the text of
+    /// an enclosing [`Role::Document`] by a single U+FFFC OBJECT
+    /// REPLACEMENT CHARACTER, because it has no text of its own.
+    /// This is true of leaf nodes other than text and generic containers,
+    /// e.g. images and buttons, unless their text is synthesized from
+    /// their name or value, as for a heading. The document must be
+    /// the nearest ancestor that supports text ranges; an image inside
+    /// a text field isn't part of any text. Like an inline text box,
+    /// an embedded object may be linked to the boxes on the same line
+    /// with `previous_on_line` and `next_on_line`. If it isn't, it's on
+    /// the line of the box before it when that line continues after it,
+    /// and on a line of its own otherwise.
+    pub fn is_embedded_object(&self) -> bool {
+        is_embedded_object_candidate(self)
+            && !self.has_synthesized_text()
+            && matches!(self.text_document(), Some(document) if document.role() == Role::Document)
+    }
+
+    /// Returns the range of this node's text that represents `child`,
+    /// which may be any descendant: the placeholder for an embedded
+    /// object, or the text of e.g. a link. Returns `None` if the child
+    /// isn't represented in the text.
+    pub fn range_from_child(&self, child: &Node) -> Option<Range<'a>> {
+        let mut boxes = self
+            .inline_text_boxes()
+            .filter(|node| node.is_descendant_of(child));
+        let first = boxes.next()?;
+        let last = boxes.next_back().unwrap_or(first);
+        Some(Range::new(
+            *self,
+            InnerPosition {
+                node: first,
+                character_index: 0,
+            },
+            InnerPosition {
+                node: last,
+                character_index: last.text_box_character_lengths().len(),
+            },
+        ))
+    }
+
+    /// Returns the links in this node's text, in document order,
+    /// not including links nested in other links.
+    pub fn hyperlinks(&self) -> Vec<Node<'a>> {
+        let mut result = Vec::<Node<'a>>::new();
+        for node in self.inline_text_boxes() {
+            if let Some(link) = enclosing_link(self, node) {
+                if result.last().map(|last| last.id()) != Some(link.id()) {
+                    result.push(link);
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the formatting of this node's text. For the root of
+    /// a document, this is the document's default formatting.
+    pub fn text_format(&self) -> Format<'a> {
+        Format {
+            font_family: self.font_family(),
+            font_size: self.font_size(),
+            font_weight: self.font_weight(),
+            bold: self.is_bold(),
+            italic: self.is_italic(),
+            background_color: self.background_color(),
+            foreground_color: self.foreground_color(),
+            overline: self.overline(),
+            strikethrough: self.strikethrough(),
+            underline: self.underline(),
+            vertical_offset: self.vertical_offset(),
+            language: self.language(),
+            is_spelling_error: self.data().is_spelling_error,
+            is_grammar_error: self.data().is_grammar_error,
+        }
+    }
+
+    /// Returns the nearest ancestor that supports text ranges.
+    pub(crate) fn text_document(&self) -> Option<Node<'a>> {
+        let mut current = self.parent();
+        while let Some(node) = current {
+            if node.supports_text_ranges() {
+                return Some(node);
+            }
+            current = node.parent();
+        }
+        None
+    }
+
+    /// Returns the node's text, or an empty string if it doesn't
+    /// support text ranges, e.g. because its last inline text box
+    /// was removed.
+    pub(crate) fn document_text(&self) -> String {
+        if self.supports_text_ranges() {
+            self.document_range().text()
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn supports_text_ranges(&self) -> bool {
+        (matches!(
+            self.role(),
+            Role::StaticText | Role::TextField | Role::Document | Role::SpinButton
+        ) && self.inline_text_boxes().next().is_some())
+            || self.has_synthesized_text()
+    }
+
+    fn document_start(&self) -> InnerPosition<'a> {
+        let node = self.inline_text_boxes().next().unwrap();
+        InnerPosition {
             node,
             character_index: 0,
         }
@@ -842,11 +1976,11 @@ impl<'a> Node<'a> {
         let node = self.inline_text_boxes().next_back().unwrap();
         InnerPosition {
             node,
-            character_index: node.data().character_lengths.len(),
+            character_index: node.text_box_character_lengths().len(),
         }
     }
 
-    pub fn document_range(&self) -> Range {
+    pub fn document_range(&self) -> Range<'a> {
         let start = self.document_start();
         let end = self.document_end();
         Range::new(*self, start, end)
@@ -864,6 +1998,29 @@ impl<'a> Node<'a> {
         })
     }
 
+    /// Returns the annotations on this node's text. Annotations whose
+    /// endpoints aren't inline text boxes inside this node are skipped.
+    pub fn text_annotations(&self) -> impl Iterator<Item = Annotation<'a>> + 'a {
+        let node = *self;
+        let state: &'a NodeState = self.state;
+        let upgrade = move |weak| {
+            InnerPosition::upgrade(node.tree_state, weak)
+                .filter(|pos| pos.node.is_descendant_of(&node))
+        };
+        state
+            .data()
+            .text_annotations
+            .iter()
+            .filter_map(move |data| {
+                let anchor = upgrade(data.anchor)?;
+                let focus = upgrade(data.focus)?;
+                Some(Annotation {
+                    data,
+                    range: Range::new(node, anchor, focus),
+                })
+            })
+    }
+
     pub fn text_selection_focus(&self) -> Option<Position> {
         self.data().text_selection.map(|selection| {
             let focus = InnerPosition::upgrade(self.tree_state, selection.focus).unwrap();
@@ -878,8 +2035,11 @@ impl<'a> Node<'a> {
     /// in this node's coordinate space.
     pub fn text_position_at_point(&self, point: Point) -> Position {
         let id = self.id();
-        if let Some((node, point)) = self.hit_test(point, &move |node| text_node_filter(id, node)) {
-            if node.role() == Role::InlineTextBox {
+        let role = self.role();
+        if let Some((node, point)) =
+            self.hit_test(point, &move |node| text_node_filter(id, role, node))
+        {
+            if node.id() != id {
                 let pos = InnerPosition {
                     node,
                     character_index: character_index_at_point(&node, point),
@@ -891,52 +2051,45 @@ impl<'a> Node<'a> {
             }
         }
 
-        // The following tests can assume that the point is not within
-        // any inline text box.
-
-        if let Some(node) = self.inline_text_boxes().next() {
+        // The point isn't within any inline text box or embedded object,
+        // so use the nearest one, first by distance across lines (along
+        // the block axis), then by distance along the line. A point past
+        // either end of a line, even one that mixes text directions, thus
+        // resolves to the edge of the visually nearest run on that line.
+        let mut nearest: Option<(Node, f64, f64)> = None;
+        for node in self.inline_text_boxes() {
             if let Some(rect) = node.bounding_box_in_coordinate_space(self) {
-                let origin = rect.origin();
-                if point.x < origin.x || point.y < origin.y {
-                    return Position {
-                        root_node: *self,
-                        inner: self.document_start(),
-                    };
+                let direction = node.text_direction().unwrap_or(TextDirection::LeftToRight);
+                let x_distance = distance_outside(point.x, rect.x0, rect.x1);
+                let y_distance = distance_outside(point.y, rect.y0, rect.y1);
+                let (block_distance, inline_distance) = if is_vertical(direction) {
+                    (x_distance, y_distance)
+                } else {
+                    (y_distance, x_distance)
+                };
+                let is_nearer = match nearest {
+                    Some((_, nearest_block_distance, nearest_inline_distance)) => {
+                        block_distance < nearest_block_distance
+                            || (block_distance == nearest_block_distance
+                                && inline_distance < nearest_inline_distance)
+                    }
+                    None => true,
+                };
+                if is_nearer {
+                    nearest = Some((node, block_distance, inline_distance));
                 }
             }
         }
 
-        for node in self.inline_text_boxes().rev() {
-            if let Some(rect) = node.bounding_box_in_coordinate_space(self) {
-                if let Some(direction) = node.data().text_direction {
-                    let is_past_end = match direction {
-                        TextDirection::LeftToRight => {
-                            point.y >= rect.y0 && point.y < rect.y1 && point.x >= rect.x1
-                        }
-                        TextDirection::RightToLeft => {
-                            point.y >= rect.y0 && point.y < rect.y1 && point.x < rect.x0
-                        }
-                        // Note: The following directions assume that the rectangle,
-                        // in the root node's coordinate space, is y-down. TBD: Will we
-                        // ever encounter a case where this isn't true?
-                        TextDirection::TopToBottom => {
-                            point.x >= rect.x0 && point.x < rect.x1 && point.y >= rect.y1
-                        }
-                        TextDirection::BottomToTop => {
-                            point.x >= rect.x0 && point.x < rect.x1 && point.y < rect.y0
-                        }
-                    };
-                    if is_past_end {
-                        return Position {
-                            root_node: *self,
-                            inner: InnerPosition {
-                                node,
-                                character_index: node.data().character_lengths.len(),
-                            },
-                        };
-                    }
-                }
-            }
+        if let Some((node, _, _)) = nearest {
+            let point = node.relative_transform(self).inverse() * point;
+            return Position {
+                root_node: *self,
+                inner: InnerPosition {
+                    node,
+                    character_index: character_index_at_point(&node, point),
+                },
+            };
         }
 
         Position {
@@ -945,7 +2098,7 @@ impl<'a> Node<'a> {
         }
     }
 
-    pub fn line_range_from_index(&self, line_index: usize) -> Option<Range> {
+    pub fn line_range_from_index(&self, line_index: usize) -> Option<Range<'a>> {
         let mut pos = self.document_range().start();
 
         if line_index > 0 {
@@ -968,24 +2121,28 @@ impl<'a> Node<'a> {
         Some(Range::new(*self, pos.inner, end.inner))
     }
 
-    pub fn text_position_from_global_utf16_index(&self, index: usize) -> Option<Position> {
+    fn text_position_from_global_index(
+        &self,
+        index: usize,
+        length: fn(&str) -> usize,
+    ) -> Option<Position<'a>> {
         let mut total_length = 0usize;
         for node in self.inline_text_boxes() {
-            let node_text = node.value().unwrap();
-            let node_text_length = node_text.chars().map(char::len_utf16).sum::<usize>();
+            let node_text = node.text_box_value();
+            let node_text_length = length(node_text);
             let new_total_length = total_length + node_text_length;
             if index >= total_length && index < new_total_length {
                 let index = index - total_length;
                 let mut utf8_length = 0usize;
-                let mut utf16_length = 0usize;
+                let mut offset = 0usize;
                 for (character_index, utf8_char_length) in
-                    node.data().character_lengths.iter().enumerate()
+                    node.text_box_character_lengths().iter().enumerate()
                 {
                     let new_utf8_length = utf8_length + (*utf8_char_length as usize);
                     let char_str = &node_text[utf8_length..new_utf8_length];
-                    let utf16_char_length = char_str.chars().map(char::len_utf16).sum::<usize>();
-                    let new_utf16_length = utf16_length + utf16_char_length;
-                    if index >= utf16_length && index < new_utf16_length {
+                    let char_length = length(char_str);
+                    let new_offset = offset + char_length;
+                    if index >= offset && index < new_offset {
                         return Some(Position {
                             root_node: *self,
                             inner: InnerPosition {
@@ -995,7 +2152,7 @@ impl<'a> Node<'a> {
                         });
                     }
                     utf8_length = new_utf8_length;
-                    utf16_length = new_utf16_length;
+                    offset = new_offset;
                 }
                 panic!("index out of range");
             }
@@ -1009,12 +2166,70 @@ impl<'a> Node<'a> {
         }
         None
     }
+
+    /// Returns the position before the character at `index`,
+    /// counted as by [`Position::to_global_character_index`].
+    pub fn text_position_from_global_character_index(&self, index: usize) -> Option<Position<'a>> {
+        let mut total_length = 0usize;
+        for node in self.inline_text_boxes() {
+            let node_length = node.text_box_character_lengths().len();
+            if index < total_length + node_length {
+                return Some(Position {
+                    root_node: *self,
+                    inner: InnerPosition {
+                        node,
+                        character_index: index - total_length,
+                    },
+                });
+            }
+            total_length += node_length;
+        }
+        (index == total_length).then(|| Position {
+            root_node: *self,
+            inner: self.document_end(),
+        })
+    }
+
+    /// Returns the position before the character containing the code point
+    /// at `index`.
+    pub fn text_position_from_global_code_point_index(&self, index: usize) -> Option<Position<'a>> {
+        self.text_position_from_global_index(index, code_point_length)
+    }
+
+    /// Returns the position before the character containing the byte
+    /// at `index` in the UTF-8 encoding of the text.
+    pub fn text_position_from_global_byte_index(&self, index: usize) -> Option<Position<'a>> {
+        self.text_position_from_global_index(index, str::len)
+    }
+
+    pub fn text_position_from_global_utf16_index(&self, index: usize) -> Option<Position<'a>> {
+        self.text_position_from_global_index(index, utf16_length)
+    }
+
+    /// Returns the position `column` characters after the start of
+    /// the line at `line_index`, or `None` if the line doesn't exist
+    /// or is shorter than that. The inverse of
+    /// [`Position::to_line_and_column`].
+    pub fn text_position_from_line_and_column(
+        &self,
+        line_index: usize,
+        column: usize,
+    ) -> Option<Position<'a>> {
+        let line = self.line_range_from_index(line_index)?;
+        let start = line.start().to_global_character_index();
+        let end = line.end().to_global_character_index();
+        match (start + column).cmp(&end) {
+            Ordering::Less => self.text_position_from_global_character_index(start + column),
+            Ordering::Equal => Some(line.end()),
+            Ordering::Greater => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use accesskit::kurbo::{Point, Rect};
-    use accesskit::{NodeId, TextSelection};
+    use accesskit::{NodeId, TextDirection, TextSelection};
     use std::{num::NonZeroU128, sync::Arc};
 
     use crate::tests::NullActionHandler;
@@ -1883,4 +3098,1486 @@ mod tests {
 
         assert!(node.text_position_from_global_utf16_index(98).is_none());
     }
+
+    #[test]
+    fn find_text() {
+        let tree = main_multiline_tree(None);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_2).unwrap();
+        let range = node.document_range();
+
+        // A match can span inline text boxes and multi-byte characters.
+        let found = range.find_text("wrap to", false, false).unwrap();
+        assert_eq!(found.text(), "wrap to");
+        let found = range.find_text("is\u{a0}long", false, false).unwrap();
+        assert_eq!(found.text(), "is\u{a0}long");
+        assert_eq!(found.start().to_global_utf16_index(), 15);
+        let found = range.find_text("line\u{1f60a}\n", false, false).unwrap();
+        assert_eq!(found.text(), "line\u{1f60a}\n");
+        assert_eq!(found.end().to_global_utf16_index(), 97);
+
+        assert!(range.find_text("PARAGRAPH", false, false).is_none());
+        let found = range.find_text("PARAGRAPH", false, true).unwrap();
+        assert_eq!(found.text(), "paragraph");
+        assert_eq!(found.start().to_global_utf16_index(), 5);
+        let found = range.find_text("PARAGRAPH", true, true).unwrap();
+        assert_eq!(found.text(), "paragraph");
+        assert_eq!(found.start().to_global_utf16_index(), 63);
+
+        // The search is confined to the range.
+        let mut first_line = range;
+        first_line.set_end(range.start().forward_to_line_end());
+        assert!(first_line.find_text("another", false, false).is_none());
+        let found = first_line.find_text("a", true, false).unwrap();
+        assert_eq!(found.start().to_global_utf16_index(), 35);
+
+        assert!(range.find_text("", false, false).is_none());
+    }
+
+    #[test]
+    fn find_text_overlapping_a_rejected_match() {
+        let tree = lines_tree(&[vec!["a", "a", "a\u{301}"]]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        let range = node.document_range();
+
+        // The last occurrence of "aa" ends inside the last character,
+        // but the one before it, which overlaps it, is a valid match.
+        let found = range.find_text("aa", true, false).unwrap();
+        assert_eq!(found.start().to_global_character_index(), 0);
+        assert_eq!(found.end().to_global_character_index(), 2);
+        let found = range.find_text("aa", false, false).unwrap();
+        assert_eq!(found.start().to_global_character_index(), 0);
+    }
+
+    #[test]
+    fn format_runs() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let text_box = |value: &str, bold, is_spelling_error| {
+            Arc::new(Node {
+                role: Role::InlineTextBox,
+                value: Some(value.into()),
+                character_lengths: vec![1; value.len()].into(),
+                word_lengths: vec![value.len() as u8].into(),
+                bold,
+                is_spelling_error,
+                ..Default::default()
+            })
+        };
+        let update = TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::TextField,
+                        children: vec![NODE_ID_2, NODE_ID_3, NODE_ID_4, NODE_ID_5, NODE_ID_6],
+                        font_family: Some("Sans".into()),
+                        font_size: Some(12.0),
+                        ..Default::default()
+                    }),
+                ),
+                (NODE_ID_2, text_box("Hello ", false, false)),
+                (NODE_ID_3, text_box("bold", true, false)),
+                (NODE_ID_4, text_box(" and ", false, false)),
+                (NODE_ID_5, text_box("plain ", false, false)),
+                (NODE_ID_6, text_box("wrng", false, true)),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: None,
+        };
+        let tree = crate::Tree::new(update, Box::new(NullActionHandler {}));
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        assert_eq!(node.text_format().font_family, Some("Sans"));
+        let range = node.document_range();
+
+        let runs = range.format_runs();
+        let texts = runs
+            .iter()
+            .map(|(range, _)| range.text())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["Hello ", "bold", " and plain ", "wrng"]);
+        for (_, format) in &runs {
+            assert_eq!(format.font_family, Some("Sans"));
+            assert_eq!(format.font_size, Some(12.0));
+        }
+        assert!(runs[1].1.bold);
+        assert!(!runs[2].1.bold);
+        assert!(runs[3].1.is_spelling_error);
+
+        let found = range.find_attribute(|format| format.bold, &false, false);
+        assert_eq!(found.unwrap().text(), "Hello ");
+        let found = range.find_attribute(|format| format.bold, &false, true);
+        assert_eq!(found.unwrap().text(), " and plain wrng");
+        let found = range.find_attribute(|format| format.is_spelling_error, &true, false);
+        assert_eq!(found.unwrap().text(), "wrng");
+        assert!(range
+            .find_attribute(|format| format.italic, &true, false)
+            .is_none());
+
+        // Runs are clipped to the range.
+        let start = range
+            .start()
+            .forward_to_character_end()
+            .forward_to_character_end();
+        let end = start.forward_to_word_end().forward_to_character_end();
+        let mut range = start.to_degenerate_range();
+        assert_eq!(range.format_runs().len(), 1);
+        range.set_end(end);
+        let texts = range
+            .format_runs()
+            .iter()
+            .map(|(range, _)| range.text())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["llo ", "b"]);
+    }
+
+    #[test]
+    fn annotations() {
+        use accesskit::{
+            Node, Role, TextAnnotation, TextAnnotationKind, TextPosition, Tree, TreeUpdate,
+        };
+
+        let text_box = |value: &str| {
+            Arc::new(Node {
+                role: Role::InlineTextBox,
+                value: Some(value.into()),
+                character_lengths: vec![1; value.len()].into(),
+                word_lengths: vec![value.len() as u8].into(),
+                ..Default::default()
+            })
+        };
+        let annotation = |kind, anchor, focus, details| TextAnnotation {
+            kind,
+            anchor,
+            focus,
+            details,
+        };
+        let spelling_error = annotation(
+            TextAnnotationKind::SpellingError,
+            TextPosition {
+                node: NODE_ID_2,
+                character_index: 0,
+            },
+            TextPosition {
+                node: NODE_ID_2,
+                character_index: 4,
+            },
+            None,
+        );
+        // The endpoints are in reverse order.
+        let search_match = annotation(
+            TextAnnotationKind::SearchMatch,
+            TextPosition {
+                node: NODE_ID_3,
+                character_index: 2,
+            },
+            TextPosition {
+                node: NODE_ID_2,
+                character_index: 3,
+            },
+            Some(NODE_ID_4),
+        );
+        let text_field = |text_annotations| {
+            Arc::new(Node {
+                role: Role::TextField,
+                children: vec![NODE_ID_2, NODE_ID_3, NODE_ID_4],
+                text_annotations,
+                ..Default::default()
+            })
+        };
+        let update = TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    text_field(vec![spelling_error.clone(), search_match.clone()]),
+                ),
+                (NODE_ID_2, text_box("Helo ")),
+                (NODE_ID_3, text_box("wrld")),
+                (
+                    NODE_ID_4,
+                    Arc::new(Node {
+                        role: Role::Tooltip,
+                        name: Some("Match 1 of 1".into()),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: None,
+        };
+        let tree = crate::Tree::new(update, Box::new(NullActionHandler {}));
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        assert_eq!(node.text_annotations().count(), 2);
+        let kinds = |annotations: Vec<super::Annotation>| {
+            annotations
+                .iter()
+                .map(|annotation| annotation.kind())
+                .collect::<Vec<_>>()
+        };
+
+        let range = node.document_range();
+        let mut pos = range.start();
+        for _ in 0..3 {
+            pos = pos.forward_to_character_end();
+        }
+        assert_eq!(
+            kinds(pos.annotations()),
+            vec![
+                TextAnnotationKind::SpellingError,
+                TextAnnotationKind::SearchMatch
+            ]
+        );
+        let search_match_range = pos.annotations()[1].range();
+        assert_eq!(search_match_range.text(), "o wr");
+        assert_eq!(
+            pos.annotations()[1].details().unwrap().name().as_deref(),
+            Some("Match 1 of 1")
+        );
+        pos = pos.forward_to_character_end();
+        assert_eq!(
+            kinds(pos.annotations()),
+            vec![TextAnnotationKind::SearchMatch]
+        );
+        assert!(range.end().annotations().is_empty());
+
+        let mut last_word = range;
+        last_word.set_start(range.end().backward_to_word_start());
+        assert_eq!(last_word.text(), "wrld");
+        assert_eq!(
+            kinds(last_word.annotations()),
+            vec![TextAnnotationKind::SearchMatch]
+        );
+        last_word.set_start(
+            last_word
+                .start()
+                .forward_to_character_end()
+                .forward_to_character_end(),
+        );
+        assert!(last_word.annotations().is_empty());
+        drop(state);
+
+        let receiver = tree.subscribe();
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_1, text_field(vec![search_match.clone()]))],
+            tree: None,
+            focus: None,
+        });
+        let changes = receiver
+            .try_iter()
+            .filter_map(|event| match event.change {
+                crate::TreeChange::TextAnnotationAdded { annotation, .. } => {
+                    Some((true, annotation))
+                }
+                crate::TreeChange::TextAnnotationRemoved { annotation, .. } => {
+                    Some((false, annotation))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![(false, spelling_error)]);
+    }
+
+    #[test]
+    fn text_deltas() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        use super::Delta;
+
+        let nodes = |lines: &[&str]| {
+            let box_ids = (0..lines.len())
+                .map(|i| NodeId(NonZeroU128::new(i as u128 + 2).unwrap()))
+                .collect::<Vec<_>>();
+            let mut nodes = vec![(
+                NODE_ID_1,
+                Arc::new(Node {
+                    role: Role::TextField,
+                    children: box_ids.clone(),
+                    ..Default::default()
+                }),
+            )];
+            for (id, line) in box_ids.into_iter().zip(lines) {
+                nodes.push((
+                    id,
+                    Arc::new(Node {
+                        role: Role::InlineTextBox,
+                        value: Some((*line).into()),
+                        character_lengths: line.chars().map(|c| c.len_utf8() as u8).collect(),
+                        word_lengths: vec![line.chars().count() as u8].into(),
+                        ..Default::default()
+                    }),
+                ));
+            }
+            nodes
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: nodes(&["Hello"]),
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let receiver = tree.subscribe();
+        let update = |lines: &[&str]| {
+            tree.update(TreeUpdate {
+                nodes: nodes(lines),
+                tree: None,
+                focus: None,
+            });
+            receiver
+                .try_iter()
+                .filter_map(|event| match event.change {
+                    crate::TreeChange::TextChanged { id, delta } if id == NODE_ID_1 => Some(delta),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let delta = |offset, removed: &str, inserted: &str| Delta {
+            offset,
+            removed: removed.into(),
+            inserted: inserted.into(),
+        };
+
+        // Typing
+        assert_eq!(update(&["Hello!"]), vec![delta(5, "", "!")]);
+        // Deletion
+        assert_eq!(update(&["ello!"]), vec![delta(0, "H", "")]);
+        // Pasting text that wraps to a new line
+        assert_eq!(
+            update(&["ello and ", "goodbye!"]),
+            vec![delta(4, "", " and goodbye")]
+        );
+        // Wrapping the same text differently
+        assert!(update(&["ello ", "and goodbye!"]).is_empty());
+        // Replacing all of the text, which removes a line
+        assert_eq!(
+            update(&["Hi\u{e9}!"]),
+            vec![delta(0, "ello and goodbye", "Hi\u{e9}")]
+        );
+        // The offset counts characters, not bytes.
+        assert_eq!(update(&["Hi\u{e9}?!"]), vec![delta(3, "", "?")]);
+        // Typing a character next to an identical one
+        assert_eq!(update(&["Hi\u{e9}??!"]), vec![delta(4, "", "?")]);
+    }
+
+    #[test]
+    fn text_deltas_in_document_order() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let field_ids = (0..4u128)
+            .map(|i| NodeId(NonZeroU128::new(i * 2 + 2).unwrap()))
+            .collect::<Vec<_>>();
+        let nodes = |text: &str, x: f64| {
+            let mut nodes = vec![(
+                NODE_ID_1,
+                Arc::new(Node {
+                    role: Role::Window,
+                    children: field_ids.clone(),
+                    ..Default::default()
+                }),
+            )];
+            for field_id in &field_ids {
+                let box_id = NodeId(field_id.0.checked_add(1).unwrap());
+                nodes.push((
+                    *field_id,
+                    Arc::new(Node {
+                        role: Role::TextField,
+                        children: vec![box_id],
+                        ..Default::default()
+                    }),
+                ));
+                nodes.push((
+                    box_id,
+                    Arc::new(Node {
+                        role: Role::InlineTextBox,
+                        bounds: Some(Rect::new(x, 0.0, x + 10.0, 10.0)),
+                        value: Some(text.into()),
+                        character_lengths: vec![1; text.len()].into(),
+                        word_lengths: vec![text.len() as u8].into(),
+                        ..Default::default()
+                    }),
+                ));
+            }
+            nodes
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: nodes("a", 0.0),
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let receiver = tree.subscribe();
+        let update = |text: &str, x: f64| {
+            tree.update(TreeUpdate {
+                nodes: nodes(text, x),
+                tree: None,
+                focus: None,
+            });
+            receiver
+                .try_iter()
+                .filter_map(|event| match event.change {
+                    crate::TreeChange::TextChanged { id, .. } => Some(id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Moving the boxes doesn't change any text.
+        assert!(update("a", 5.0).is_empty());
+        assert_eq!(update("b", 5.0), field_ids);
+        assert_eq!(update("c", 0.0), field_ids);
+    }
+
+    #[test]
+    fn live_ranges() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let nodes = |text: &str| {
+            vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::TextField,
+                        children: vec![NODE_ID_2],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_2,
+                    Arc::new(Node {
+                        role: Role::InlineTextBox,
+                        value: Some(text.into()),
+                        character_lengths: text.chars().map(|c| c.len_utf8() as u8).collect(),
+                        word_lengths: vec![text.chars().count() as u8].into(),
+                        ..Default::default()
+                    }),
+                ),
+            ]
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: nodes("Hello world"),
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let update = |text: &str| {
+            tree.update(TreeUpdate {
+                nodes: nodes(text),
+                tree: None,
+                focus: None,
+            })
+        };
+        let text = |range: &super::LiveRange| range.upgrade(&tree.read()).unwrap().text();
+
+        let range = {
+            let state = tree.read();
+            let node = state.node_by_id(NODE_ID_1).unwrap();
+            let start = node.text_position_from_global_utf16_index(6).unwrap();
+            let mut range = node.document_range();
+            range.set_start(start);
+            tree.track_text_range(&range)
+        };
+        let caret = {
+            let state = tree.read();
+            let node = state.node_by_id(NODE_ID_1).unwrap();
+            let pos = node.text_position_from_global_utf16_index(5).unwrap();
+            tree.track_text_range(&pos.to_degenerate_range())
+        };
+        assert_eq!(text(&range), "world");
+        assert_eq!(range.offsets(), (6, 11));
+
+        // Before the range, including at its start
+        update("Hello, world");
+        assert_eq!(range.offsets(), (7, 12));
+        assert_eq!(text(&range), "world");
+        update("\u{e9}Hello, world");
+        assert_eq!(range.offsets(), (8, 13));
+        assert_eq!(text(&range), "world");
+        update("Hello, world");
+        assert_eq!(text(&range), "world");
+        // A degenerate range at an insertion moves past the inserted text.
+        assert_eq!(caret.offsets(), (6, 6));
+
+        // Inside the range
+        update("Hello, wonderful world");
+        assert_eq!(text(&range), "wonderful world");
+        update("Hello, world");
+        assert_eq!(text(&range), "world");
+
+        // After the range, including at its end
+        update("Hello, world!");
+        assert_eq!(text(&range), "world");
+        update("Hello, world");
+        assert_eq!(text(&range), "world");
+
+        // Overlapping the start of the range
+        update("Hello, orld");
+        assert_eq!(range.offsets(), (7, 11));
+        assert_eq!(text(&range), "orld");
+
+        // Spanning the range, which collapses it
+        update("Hello");
+        assert_eq!(range.offsets(), (5, 5));
+        assert!(range.upgrade(&tree.read()).unwrap().is_degenerate());
+
+        // A clone is tracked separately.
+        let other = range.clone();
+        {
+            let state = tree.read();
+            range.set(&state.node_by_id(NODE_ID_1).unwrap().document_range());
+        }
+        assert_eq!(range.offsets(), (0, 5));
+        assert_eq!(other.offsets(), (5, 5));
+        update("Oh, Hello");
+        assert_eq!(text(&range), "Hello");
+        assert_eq!(other.offsets(), (9, 9));
+
+        // A reader may still hold the state from before an update,
+        // and create a range from it after the update was applied.
+        let old_state = tree.read();
+        let old_node = old_state.node_by_id(NODE_ID_1).unwrap();
+        let mut old_range = old_node.document_range();
+        old_range.set_start(old_node.text_position_from_global_utf16_index(4).unwrap());
+        update("Well, oh, Hello");
+        let late = tree.track_text_range(&old_range);
+        assert_eq!(late.offsets(), (10, 15));
+        assert_eq!(text(&late), "Hello");
+        late.set(&old_range);
+        assert_eq!(text(&late), "Hello");
+        // Ranges can still be upgraded with the old state.
+        assert_eq!(late.upgrade(&old_state).unwrap().text(), "Hello");
+        assert_eq!(range.upgrade(&old_state).unwrap().text(), "Hello");
+        drop(old_state);
+
+        // The tree forgets dropped ranges.
+        drop((range, caret, other, late));
+        assert!(tree.live_text_ranges.is_empty());
+    }
+
+    #[test]
+    fn embedded_objects() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let text_box = |text: &str, previous_on_line, next_on_line| {
+            Arc::new(Node {
+                role: Role::InlineTextBox,
+                value: Some(text.into()),
+                character_lengths: text.chars().map(|c| c.len_utf8() as u8).collect(),
+                word_lengths: vec![text.chars().count() as u8].into(),
+                previous_on_line,
+                next_on_line,
+                ..Default::default()
+            })
+        };
+        let parent = |role, children| {
+            Arc::new(Node {
+                role,
+                children,
+                ..Default::default()
+            })
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (NODE_ID_1, parent(Role::Document, vec![NODE_ID_2])),
+                    (
+                        NODE_ID_2,
+                        parent(
+                            Role::Paragraph,
+                            vec![NODE_ID_3, NODE_ID_5, NODE_ID_8, id(9), id(11)],
+                        ),
+                    ),
+                    (NODE_ID_3, parent(Role::StaticText, vec![NODE_ID_4])),
+                    (NODE_ID_4, text_box("Click ", None, Some(NODE_ID_7))),
+                    (NODE_ID_5, parent(Role::Link, vec![NODE_ID_6])),
+                    (NODE_ID_6, parent(Role::StaticText, vec![NODE_ID_7])),
+                    (
+                        NODE_ID_7,
+                        text_box("here", Some(NODE_ID_4), Some(NODE_ID_8)),
+                    ),
+                    (
+                        NODE_ID_8,
+                        Arc::new(Node {
+                            role: Role::Image,
+                            previous_on_line: Some(NODE_ID_7),
+                            next_on_line: Some(id(10)),
+                            ..Default::default()
+                        }),
+                    ),
+                    (id(9), parent(Role::StaticText, vec![id(10)])),
+                    (id(10), text_box(" now", Some(NODE_ID_8), None)),
+                    (id(11), parent(Role::GenericContainer, vec![])),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+        let document = state.node_by_id(NODE_ID_1).unwrap();
+        let link = state.node_by_id(NODE_ID_5).unwrap();
+        let image = state.node_by_id(NODE_ID_8).unwrap();
+
+        assert!(image.is_embedded_object());
+        assert!(!link.is_embedded_object());
+        assert!(!state.node_by_id(NODE_ID_7).unwrap().is_embedded_object());
+        assert!(!state.node_by_id(id(11)).unwrap().is_embedded_object());
+
+        let range = document.document_range();
+        assert_eq!(range.text(), "Click here\u{fffc} now");
+        assert!(range.start().is_line_start());
+        assert!(range.end().is_line_end());
+        assert!(range.start().forward_to_line_end().is_document_end());
+        let objects = range.embedded_objects();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].id(), NODE_ID_8);
+        assert_eq!(range.enclosing_node().id(), NODE_ID_2);
+
+        // From a placeholder to its object and back
+        let pos = document.text_position_from_global_utf16_index(10).unwrap();
+        assert_eq!(pos.embedded_object().unwrap().id(), NODE_ID_8);
+        assert!(pos.forward_to_character_end().embedded_object().is_none());
+        let image_range = document.range_from_child(&image).unwrap();
+        assert_eq!(image_range.text(), "\u{fffc}");
+        assert_eq!(image_range.start().to_global_utf16_index(), 10);
+        assert_eq!(image_range.enclosing_node().id(), NODE_ID_8);
+        assert!(document
+            .range_from_child(&state.node_by_id(id(11)).unwrap())
+            .is_none());
+
+        // Hyperlinks
+        let hyperlinks = document.hyperlinks();
+        assert_eq!(hyperlinks.len(), 1);
+        assert_eq!(hyperlinks[0].id(), NODE_ID_5);
+        let link_range = document.range_from_child(&link).unwrap();
+        assert_eq!(link_range.text(), "here");
+        assert_eq!(link_range.start().to_global_utf16_index(), 6);
+        assert!(link_range.embedded_objects().is_empty());
+        assert_eq!(link_range.start().hyperlink().unwrap().id(), NODE_ID_5);
+        assert!(link_range.end().hyperlink().is_none());
+        assert!(range.start().hyperlink().is_none());
+    }
+
+    #[test]
+    fn unlinked_embedded_objects() {
+        use super::Unit;
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let id = |n| NodeId(NonZeroU128::new(n).unwrap());
+        let text_box = |text: &str, previous_on_line, next_on_line| {
+            Arc::new(Node {
+                role: Role::InlineTextBox,
+                value: Some(text.into()),
+                character_lengths: text.chars().map(|c| c.len_utf8() as u8).collect(),
+                word_lengths: vec![text.chars().count() as u8].into(),
+                previous_on_line,
+                next_on_line,
+                ..Default::default()
+            })
+        };
+        let static_text = |child| {
+            Arc::new(Node {
+                role: Role::StaticText,
+                children: vec![child],
+                ..Default::default()
+            })
+        };
+        let image = || {
+            Arc::new(Node {
+                role: Role::Image,
+                ..Default::default()
+            })
+        };
+        // The first image is in the middle of the first line, and the second
+        // one is on a line of its own. Neither is linked to the text boxes.
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Document,
+                            children: vec![NODE_ID_2, NODE_ID_4, NODE_ID_5, NODE_ID_7, id(8)],
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_2, static_text(NODE_ID_3)),
+                    (NODE_ID_3, text_box("Click ", None, Some(NODE_ID_6))),
+                    (NODE_ID_4, image()),
+                    (NODE_ID_5, static_text(NODE_ID_6)),
+                    (NODE_ID_6, text_box(" now\n", Some(NODE_ID_3), None)),
+                    (NODE_ID_7, image()),
+                    (id(8), static_text(id(9))),
+                    (id(9), text_box("end", None, None)),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+        let document = state.root();
+        let range = document.document_range();
+        assert_eq!(range.text(), "Click \u{fffc} now\n\u{fffc}end");
+
+        let pos = document
+            .text_position_from_global_character_index(6)
+            .unwrap();
+        assert_eq!(pos.embedded_object().unwrap().id(), NODE_ID_4);
+        assert!(!pos.is_line_start());
+        assert!(!pos.forward_to_character_end().is_line_end());
+        assert_eq!(pos.to_line_index(), 0);
+        assert_eq!(
+            pos.enclosing_unit(Unit::Line).text(),
+            "Click \u{fffc} now\n"
+        );
+        assert_eq!(
+            range
+                .start()
+                .forward_to_line_end()
+                .to_global_character_index(),
+            12
+        );
+
+        let pos = document
+            .text_position_from_global_character_index(12)
+            .unwrap();
+        assert_eq!(pos.embedded_object().unwrap().id(), NODE_ID_7);
+        assert!(pos.is_line_start());
+        assert_eq!(pos.to_line_index(), 1);
+        assert_eq!(pos.enclosing_unit(Unit::Line).text(), "\u{fffc}");
+        assert_eq!(document.line_range_from_index(2).unwrap().text(), "end");
+    }
+
+    #[test]
+    fn nested_embedded_objects() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let image = || {
+            Arc::new(Node {
+                role: Role::Image,
+                name: Some("Icon".into()),
+                ..Default::default()
+            })
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Document,
+                            children: vec![NODE_ID_2, NODE_ID_5],
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        NODE_ID_2,
+                        Arc::new(Node {
+                            role: Role::TextField,
+                            children: vec![NODE_ID_3, NODE_ID_4],
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        NODE_ID_3,
+                        Arc::new(Node {
+                            role: Role::InlineTextBox,
+                            value: Some("ab".into()),
+                            character_lengths: vec![1, 1].into(),
+                            word_lengths: vec![2].into(),
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_4, image()),
+                    (NODE_ID_5, image()),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+
+        // The image in the text field isn't part of the field's text,
+        // so it isn't an embedded object in the document either.
+        let nested_image = state.node_by_id(NODE_ID_4).unwrap();
+        assert!(!nested_image.is_embedded_object());
+        assert_eq!(nested_image.text_document().unwrap().id(), NODE_ID_2);
+        assert!(super::InnerPosition::upgrade(
+            &state,
+            super::WeakPosition {
+                node: NODE_ID_4,
+                character_index: 0,
+            }
+        )
+        .is_none());
+        assert!(state.node_by_id(NODE_ID_5).unwrap().is_embedded_object());
+
+        let field = state.node_by_id(NODE_ID_2).unwrap();
+        assert_eq!(field.document_range().text(), "ab");
+        let document = state.root();
+        let range = document.document_range();
+        assert_eq!(range.text(), "ab\u{fffc}");
+        let embedded_objects = range.embedded_objects();
+        assert_eq!(embedded_objects.len(), 1);
+        assert_eq!(embedded_objects[0].id(), NODE_ID_5);
+    }
+
+    #[test]
+    fn enclosing_units() {
+        use super::Unit;
+
+        let tree = main_multiline_tree(None);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_2).unwrap();
+        let pos = node.text_position_from_global_character_index(5).unwrap();
+        let text = |unit| pos.enclosing_unit(unit).text();
+        assert_eq!(text(Unit::Character), "p");
+        assert_eq!(text(Unit::Word), "paragraph ");
+        assert_eq!(
+            text(Unit::Line),
+            "This paragraph is\u{a0}long enough to wrap "
+        );
+        assert_eq!(
+            text(Unit::Paragraph),
+            "This paragraph is\u{a0}long enough to wrap to another line.\n"
+        );
+        assert_eq!(
+            text(Unit::Sentence),
+            "This paragraph is\u{a0}long enough to wrap to another line.\n"
+        );
+        assert_eq!(text(Unit::Format), node.document_range().text());
+        assert_eq!(text(Unit::Page), node.document_range().text());
+        assert_eq!(text(Unit::Document), node.document_range().text());
+
+        // The end of a wrapped line is in that line.
+        let range = pos.enclosing_unit(Unit::Line);
+        assert!(range.end().enclosing_unit(Unit::Line) == range);
+        assert_eq!(range.start().to_global_character_index(), 0);
+        assert_eq!(range.end().to_global_character_index(), 38);
+
+        let pos = node.document_range().end();
+        assert!(pos.enclosing_unit(Unit::Character).is_degenerate());
+        // The document ends with an empty paragraph.
+        assert!(pos.enclosing_unit(Unit::Paragraph).is_degenerate());
+        assert_eq!(
+            pos.enclosing_unit(Unit::Document).text(),
+            node.document_range().text()
+        );
+    }
+
+    #[test]
+    fn enclosing_sentences_and_format_runs() {
+        use super::Unit;
+
+        let tree = lines_tree(
+            &["Hi there. ", "How are you? Fine."]
+                .map(|line| line.split_inclusive(|_| true).collect::<Vec<_>>()),
+        );
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        let sentence = |index| {
+            node.text_position_from_global_character_index(index)
+                .unwrap()
+                .enclosing_unit(Unit::Sentence)
+                .text()
+        };
+        assert_eq!(sentence(0), "Hi there. ");
+        assert_eq!(sentence(9), "Hi there. ");
+        assert_eq!(sentence(10), "How are you? ");
+        assert_eq!(sentence(22), "How are you? ");
+        assert_eq!(sentence(23), "Fine.");
+        // The end of the document is in the last sentence.
+        assert_eq!(sentence(28), "Fine.");
+        drop(state);
+
+        let tree = lines_tree(&[vec!["a"], vec!["b", "c"], vec!["d"]]);
+        tree.update(accesskit::TreeUpdate {
+            nodes: vec![(
+                NodeId(NonZeroU128::new(3).unwrap()),
+                Arc::new(accesskit::Node {
+                    role: accesskit::Role::InlineTextBox,
+                    value: Some("bc".into()),
+                    character_lengths: vec![1, 1].into(),
+                    word_lengths: vec![2].into(),
+                    bold: true,
+                    ..Default::default()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        let pos = node.text_position_from_global_character_index(2).unwrap();
+        assert!(!pos.is_format_start());
+        let run = pos.enclosing_unit(Unit::Format);
+        assert_eq!(run.text(), "bc");
+        assert!(run.start().is_format_start());
+        assert!(run.end().is_format_start());
+        assert_eq!(
+            run.end()
+                .forward_to_format_end()
+                .to_global_character_index(),
+            4
+        );
+        assert_eq!(
+            pos.backward_to_format_start().to_global_character_index(),
+            1
+        );
+        assert_eq!(
+            run.start()
+                .backward_to_format_start()
+                .to_global_character_index(),
+            0
+        );
+    }
+
+    const CLUSTERS: &[&str] = &[
+        "a",
+        " ",
+        "\u{e9}",
+        "e\u{301}",
+        "\u{4f60}",
+        "\u{628}",
+        "\u{5e9}",
+        "\u{915}\u{94d}\u{937}\u{93f}",
+        "\u{1d11e}",
+        "\u{1f44d}\u{1f3fd}",
+        "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}",
+        "\u{1f1ef}\u{1f1f5}",
+        "\u{1f3f3}\u{fe0f}\u{200d}\u{1f308}",
+    ];
+
+    /// Builds a document with one inline text box per line, where each
+    /// line is a sequence of characters.
+    fn lines_tree(lines: &[Vec<&str>]) -> crate::Tree {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let box_ids = (0..lines.len())
+            .map(|i| NodeId(NonZeroU128::new(i as u128 + 2).unwrap()))
+            .collect::<Vec<_>>();
+        let mut nodes = vec![(
+            NODE_ID_1,
+            Arc::new(Node {
+                role: Role::TextField,
+                children: box_ids.clone(),
+                ..Default::default()
+            }),
+        )];
+        for (id, line) in box_ids.into_iter().zip(lines) {
+            nodes.push((
+                id,
+                Arc::new(Node {
+                    role: Role::InlineTextBox,
+                    value: Some(line.concat().into()),
+                    character_lengths: line.iter().map(|c| c.len() as u8).collect(),
+                    word_lengths: vec![line.len() as u8].into(),
+                    ..Default::default()
+                }),
+            ));
+        }
+        crate::Tree::new(
+            TreeUpdate {
+                nodes,
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        )
+    }
+
+    #[test]
+    fn offset_conversions() {
+        let family = CLUSTERS[10];
+        let tree = lines_tree(&[vec!["a", family, "b"], vec![CLUSTERS[4], "c"]]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+
+        // Inside the family emoji, which is 7 code points and 25 bytes
+        let pos = node.text_position_from_global_code_point_index(4).unwrap();
+        assert_eq!(pos.to_global_character_index(), 1);
+        assert_eq!(pos.to_global_code_point_index(), 1);
+        let pos = node.text_position_from_global_byte_index(26).unwrap();
+        assert_eq!(pos.to_global_character_index(), 2);
+        assert_eq!(pos.to_global_byte_index(), 26);
+        assert_eq!(pos.to_global_utf16_index(), 12);
+
+        // Lines and columns
+        let pos = node.text_position_from_global_character_index(4).unwrap();
+        assert_eq!(pos.to_line_and_column(), (1, 1));
+        let end_of_first_line = node.line_range_from_index(0).unwrap().end();
+        assert_eq!(end_of_first_line.to_line_and_column(), (0, 3));
+        let pos = node.text_position_from_line_and_column(0, 3).unwrap();
+        assert!(pos == end_of_first_line);
+        assert!(node.text_position_from_line_and_column(0, 4).is_none());
+        assert!(node.text_position_from_line_and_column(3, 0).is_none());
+        assert!(node.text_position_from_global_character_index(6).is_none());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn offset_conversions_round_trip(
+            lines in proptest::collection::vec(
+                proptest::collection::vec(proptest::sample::select(CLUSTERS), 1..12),
+                1..6,
+            )
+        ) {
+            let tree = lines_tree(&lines);
+            let state = tree.read();
+            let node = state.node_by_id(NODE_ID_1).unwrap();
+            let characters = lines.concat();
+
+            for index in 0..=characters.len() {
+                let prefix = characters[..index].concat();
+                let pos = node.text_position_from_global_character_index(index).unwrap();
+                assert_eq!(pos.to_global_character_index(), index);
+                let code_point_index = prefix.chars().count();
+                assert_eq!(pos.to_global_code_point_index(), code_point_index);
+                assert_eq!(pos.to_global_byte_index(), prefix.len());
+                let utf16_index = prefix.encode_utf16().count();
+                assert_eq!(pos.to_global_utf16_index(), utf16_index);
+                for pos in [
+                    node.text_position_from_global_code_point_index(code_point_index),
+                    node.text_position_from_global_byte_index(prefix.len()),
+                    node.text_position_from_global_utf16_index(utf16_index),
+                ] {
+                    assert_eq!(pos.unwrap().to_global_character_index(), index);
+                }
+                if let Some(character) = characters.get(index) {
+                    // Indices within a character round down to its start.
+                    for pos in [
+                        node.text_position_from_global_code_point_index(
+                            code_point_index + character.chars().count() - 1,
+                        ),
+                        node.text_position_from_global_byte_index(prefix.len() + character.len() - 1),
+                        node.text_position_from_global_utf16_index(
+                            utf16_index + character.encode_utf16().count() - 1,
+                        ),
+                    ] {
+                        assert_eq!(pos.unwrap().to_global_character_index(), index);
+                    }
+                }
+                let (line, column) = pos.to_line_and_column();
+                let pos = node.text_position_from_line_and_column(line, column).unwrap();
+                assert_eq!(pos.to_global_character_index(), index);
+            }
+            let text = characters.concat();
+            assert!(node.text_position_from_global_character_index(characters.len() + 1).is_none());
+            assert!(node.text_position_from_global_code_point_index(text.chars().count() + 1).is_none());
+            assert!(node.text_position_from_global_byte_index(text.len() + 1).is_none());
+
+            for (line_index, line) in lines.iter().enumerate() {
+                let end = node.line_range_from_index(line_index).unwrap().end();
+                assert_eq!(end.to_line_and_column(), (line_index, line.len()));
+                let pos = node.text_position_from_line_and_column(line_index, line.len()).unwrap();
+                assert!(pos == end);
+            }
+        }
+    }
+
+    fn directional_tree(boxes: &[(TextDirection, Rect, &str)]) -> crate::Tree {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let box_ids = (0..boxes.len())
+            .map(|i| NodeId(NonZeroU128::new(i as u128 + 2).unwrap()))
+            .collect::<Vec<_>>();
+        let mut nodes = vec![(
+            NODE_ID_1,
+            Arc::new(Node {
+                role: Role::TextField,
+                children: box_ids.clone(),
+                ..Default::default()
+            }),
+        )];
+        // Every character is 10 pixels along the inline axis.
+        for (id, (direction, bounds, text)) in box_ids.into_iter().zip(boxes) {
+            nodes.push((
+                id,
+                Arc::new(Node {
+                    role: Role::InlineTextBox,
+                    bounds: Some(*bounds),
+                    value: Some((*text).into()),
+                    text_direction: Some(*direction),
+                    character_lengths: vec![1; text.len()].into(),
+                    character_positions: Some((0..text.len()).map(|i| i as f32 * 10.0).collect()),
+                    character_widths: Some(vec![10.0; text.len()].into()),
+                    word_lengths: vec![text.len() as u8].into(),
+                    ..Default::default()
+                }),
+            ));
+        }
+        crate::Tree::new(
+            TreeUpdate {
+                nodes,
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        )
+    }
+
+    fn caret_and_selection_boxes(
+        node: &crate::Node,
+        caret: usize,
+        selection: (usize, usize),
+    ) -> (Vec<Rect>, Vec<Rect>) {
+        let caret = node
+            .text_position_from_global_character_index(caret)
+            .unwrap()
+            .to_degenerate_range()
+            .bounding_boxes();
+        let mut range = node
+            .text_position_from_global_character_index(selection.0)
+            .unwrap()
+            .to_degenerate_range();
+        range.set_end(
+            node.text_position_from_global_character_index(selection.1)
+                .unwrap(),
+        );
+        (caret, range.bounding_boxes())
+    }
+
+    #[test]
+    fn right_to_left_geometry() {
+        // "a" is the rightmost character.
+        let tree = directional_tree(&[(
+            TextDirection::RightToLeft,
+            Rect::new(10.0, 0.0, 40.0, 10.0),
+            "abc",
+        )]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+
+        let (caret, selection) = caret_and_selection_boxes(&node, 1, (0, 2));
+        assert_eq!(caret, vec![Rect::new(30.0, 0.0, 30.0, 10.0)]);
+        assert_eq!(selection, vec![Rect::new(20.0, 0.0, 40.0, 10.0)]);
+        let (caret, _) = caret_and_selection_boxes(&node, 3, (0, 3));
+        assert_eq!(caret, vec![Rect::new(10.0, 0.0, 10.0, 10.0)]);
+
+        let hit = |x, y| {
+            node.text_position_at_point(Point::new(x, y))
+                .to_global_character_index()
+        };
+        assert_eq!(hit(35.0, 5.0), 0);
+        assert_eq!(hit(12.0, 5.0), 2);
+        assert_eq!(hit(45.0, 5.0), 0);
+        assert_eq!(hit(5.0, 5.0), 3);
+        assert_eq!(hit(25.0, -5.0), 1);
+    }
+
+    #[test]
+    fn vertical_geometry() {
+        let bounds = Rect::new(0.0, 10.0, 10.0, 40.0);
+
+        let tree = directional_tree(&[(TextDirection::TopToBottom, bounds, "abc")]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        let (caret, selection) = caret_and_selection_boxes(&node, 1, (1, 3));
+        assert_eq!(caret, vec![Rect::new(0.0, 20.0, 10.0, 20.0)]);
+        assert_eq!(selection, vec![Rect::new(0.0, 20.0, 10.0, 40.0)]);
+        let hit = |x, y| {
+            node.text_position_at_point(Point::new(x, y))
+                .to_global_character_index()
+        };
+        assert_eq!(hit(5.0, 15.0), 0);
+        assert_eq!(hit(5.0, 35.0), 2);
+        assert_eq!(hit(5.0, 5.0), 0);
+        assert_eq!(hit(5.0, 45.0), 3);
+
+        let tree = directional_tree(&[(TextDirection::BottomToTop, bounds, "abc")]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        let (caret, selection) = caret_and_selection_boxes(&node, 1, (0, 2));
+        assert_eq!(caret, vec![Rect::new(0.0, 30.0, 10.0, 30.0)]);
+        assert_eq!(selection, vec![Rect::new(0.0, 20.0, 10.0, 40.0)]);
+        let hit = |x, y| {
+            node.text_position_at_point(Point::new(x, y))
+                .to_global_character_index()
+        };
+        assert_eq!(hit(5.0, 35.0), 0);
+        assert_eq!(hit(5.0, 12.0), 2);
+        assert_eq!(hit(5.0, 45.0), 0);
+        assert_eq!(hit(5.0, 5.0), 3);
+    }
+
+    #[test]
+    fn vertical_lines_progressing_right_to_left() {
+        let tree = directional_tree(&[
+            (
+                TextDirection::TopToBottom,
+                Rect::new(20.0, 0.0, 30.0, 30.0),
+                "abc",
+            ),
+            (
+                TextDirection::TopToBottom,
+                Rect::new(10.0, 0.0, 20.0, 20.0),
+                "de",
+            ),
+        ]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+        let hit = |x, y| {
+            node.text_position_at_point(Point::new(x, y))
+                .to_global_character_index()
+        };
+        assert_eq!(hit(35.0, 15.0), 1);
+        assert_eq!(hit(25.0, 45.0), 3);
+        assert_eq!(hit(5.0, 15.0), 4);
+        assert_eq!(hit(15.0, 25.0), 5);
+    }
+
+    #[test]
+    fn mixed_direction_line_geometry() {
+        // Logically "abc def", displayed as "abc fed".
+        let tree = directional_tree(&[
+            (
+                TextDirection::LeftToRight,
+                Rect::new(0.0, 0.0, 40.0, 10.0),
+                "abc ",
+            ),
+            (
+                TextDirection::RightToLeft,
+                Rect::new(40.0, 0.0, 70.0, 10.0),
+                "def",
+            ),
+        ]);
+        let state = tree.read();
+        let node = state.node_by_id(NODE_ID_1).unwrap();
+
+        let (caret, selection) = caret_and_selection_boxes(&node, 5, (2, 6));
+        assert_eq!(caret, vec![Rect::new(60.0, 0.0, 60.0, 10.0)]);
+        assert_eq!(
+            selection,
+            vec![
+                Rect::new(20.0, 0.0, 40.0, 10.0),
+                Rect::new(50.0, 0.0, 70.0, 10.0)
+            ]
+        );
+
+        let hit = |x, y| {
+            node.text_position_at_point(Point::new(x, y))
+                .to_global_character_index()
+        };
+        assert_eq!(hit(-5.0, 5.0), 0);
+        assert_eq!(hit(35.0, 5.0), 3);
+        assert_eq!(hit(65.0, 5.0), 4);
+        assert_eq!(hit(45.0, 5.0), 6);
+        // Past the right end of the line, the nearest run is "def",
+        // whose start edge is on the right.
+        assert_eq!(hit(75.0, 5.0), 4);
+    }
+
+    #[test]
+    fn synthesized_text() {
+        use accesskit::{Node, Role, TextPosition, Tree, TreeUpdate};
+
+        let label = |name: &str| {
+            Arc::new(Node {
+                role: Role::StaticText,
+                bounds: Some(Rect::new(0.0, 0.0, 100.0, 20.0)),
+                name: Some(name.into()),
+                ..Default::default()
+            })
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Window,
+                            children: vec![NODE_ID_2, NODE_ID_3, NODE_ID_4],
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_2, label("Hello, world! Bye.")),
+                    (
+                        NODE_ID_3,
+                        Arc::new(Node {
+                            role: Role::TextField,
+                            bounds: Some(Rect::new(0.0, 20.0, 100.0, 40.0)),
+                            text_selection: Some(TextSelection {
+                                anchor: TextPosition {
+                                    node: NODE_ID_3,
+                                    character_index: 0,
+                                },
+                                focus: TextPosition {
+                                    node: NODE_ID_3,
+                                    character_index: 0,
+                                },
+                            }),
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        NODE_ID_4,
+                        Arc::new(Node {
+                            role: Role::Button,
+                            name: Some("OK".into()),
+                            ..Default::default()
+                        }),
+                    ),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+
+        let range = {
+            let state = tree.read();
+            assert!(!state.node_by_id(NODE_ID_4).unwrap().supports_text_ranges());
+
+            let node = state.node_by_id(NODE_ID_2).unwrap();
+            assert!(node.supports_text_ranges());
+            assert!(node.is_read_only());
+            assert!(node.detached().is_read_only());
+            let range = node.document_range();
+            assert_eq!(range.text(), "Hello, world! Bye.");
+            assert_eq!(node.line_range_from_index(0).unwrap().text(), range.text());
+            assert!(node.line_range_from_index(1).is_none());
+            assert!(range.start().is_line_start());
+            assert!(range.end().is_line_end());
+            assert!(range.end().is_document_end());
+            assert_eq!(range.end().to_line_and_column(), (0, 18));
+            assert_eq!(
+                range.bounding_boxes(),
+                vec![Rect::new(0.0, 0.0, 100.0, 20.0)]
+            );
+            assert!(range.embedded_objects().is_empty());
+            assert_eq!(range.enclosing_node().id(), NODE_ID_2);
+
+            // Words include the punctuation and spaces that follow them.
+            let word_end = range.start().forward_to_word_end();
+            assert_eq!(word_end.to_global_character_index(), 7);
+            let pos = word_end.forward_to_word_end();
+            assert_eq!(pos.to_global_character_index(), 14);
+            assert!(pos.is_word_start());
+
+            // There's no character geometry.
+            let mut range = word_end.to_degenerate_range();
+            range.set_end(pos);
+            assert_eq!(range.text(), "world! ");
+            assert!(range.bounding_boxes().is_empty());
+            let pos = node.text_position_at_point(Point::new(50.0, 10.0));
+            assert!(pos.is_document_start());
+            let pos = node.text_position_at_point(Point::new(150.0, 10.0));
+            assert!(pos.is_document_end());
+
+            let field = state.node_by_id(NODE_ID_3).unwrap();
+            assert!(field.supports_text_ranges());
+            assert!(!field.is_read_only());
+            let selection = field.text_selection().unwrap();
+            assert!(selection.is_degenerate());
+            assert_eq!(field.document_range().text(), "");
+            assert_eq!(
+                selection.bounding_boxes(),
+                vec![Rect::new(0.0, 20.0, 100.0, 40.0)]
+            );
+
+            tree.track_text_range(&range)
+        };
+
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_2, label("Oh, hello, world! Bye."))],
+            tree: None,
+            focus: None,
+        });
+        let state = tree.read();
+        assert_eq!(range.upgrade(&state).unwrap().text(), "world! ");
+        let node = state.node_by_id(NODE_ID_2).unwrap();
+        assert_eq!(node.document_range().text(), "Oh, hello, world! Bye.");
+    }
+
+    #[test]
+    fn synthesized_text_lengths() {
+        let text = super::SynthesizedText::new(format!("{} {}", CLUSTERS[10], "x".repeat(300)));
+        assert_eq!(text.character_lengths.len(), 302);
+        assert_eq!(text.character_lengths[0] as usize, CLUSTERS[10].len());
+        assert_eq!(&text.word_lengths[..], &[255, 47]);
+        let text = super::SynthesizedText::new("ab  cd".into());
+        assert_eq!(&text.word_lengths[..], &[4, 2]);
+        let text = super::SynthesizedText::new(String::new());
+        assert!(text.character_lengths.is_empty());
+        assert_eq!(&text.word_lengths[..], &[0]);
+    }
+
+    #[test]
+    fn synthesized_text_in_document() {
+        use accesskit::{Node, Role, Tree, TreeUpdate};
+
+        let named = |role: Role, name: &str| {
+            Arc::new(Node {
+                role,
+                name: Some(name.into()),
+                ..Default::default()
+            })
+        };
+        let tree = crate::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Document,
+                            children: vec![NODE_ID_2, NODE_ID_3, NODE_ID_4],
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_2, named(Role::StaticText, "Hello, ")),
+                    (NODE_ID_3, named(Role::Heading, "world")),
+                    (NODE_ID_4, named(Role::Image, "Logo")),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+
+        {
+            let state = tree.read();
+            let document = state.root();
+            let range = document.document_range();
+            assert_eq!(range.text(), "Hello, world\u{fffc}");
+            let embedded_objects = range.embedded_objects();
+            assert_eq!(embedded_objects.len(), 1);
+            assert_eq!(embedded_objects[0].id(), NODE_ID_4);
+            let heading = state.node_by_id(NODE_ID_3).unwrap();
+            assert!(!heading.is_embedded_object());
+            assert_eq!(heading.document_range().text(), "world");
+            let word_end = range.start().forward_to_word_end();
+            assert_eq!(word_end.to_global_character_index(), 7);
+            assert_eq!(
+                word_end.forward_to_word_end().to_global_character_index(),
+                12
+            );
+        }
+
+        let receiver = tree.subscribe();
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_3, named(Role::Heading, "there"))],
+            tree: None,
+            focus: None,
+        });
+        let ids = receiver
+            .try_iter()
+            .filter_map(|event| match event.change {
+                crate::TreeChange::TextChanged { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![NODE_ID_1, NODE_ID_3]);
+        let state = tree.read();
+        assert_eq!(state.root().document_range().text(), "Hello, there\u{fffc}");
+    }
 }
@@ -5,26 +5,44 @@
 
 use accesskit::kurbo::Point;
 use accesskit::{
-    Action, ActionData, ActionHandler, ActionRequest, Live, Node as NodeData, NodeId,
-    TextSelection, Tree as TreeData, TreeUpdate,
+    Action, ActionData, ActionHandler, ActionRequest, Node as NodeData, NodeId, Role,
+    TextAnnotation as TextAnnotationData, TextSelection, Tree as TreeData, TreeUpdate,
 };
-use parking_lot::{RwLock, RwLockWriteGuard};
+use arc_swap::{ArcSwap, Guard};
+use parking_lot::Mutex;
 use std::{
     collections::{HashMap, HashSet},
     ops::Deref,
-    sync::Arc,
+    sync::{mpsc, Arc},
 };
 
 use crate::{
-    node::{DetachedNode, Node, NodeState, ParentAndIndex},
-    text::{Position as TextPosition, Range as TextRange},
+    changes::{Event as ChangeEvent, EventStream, Recorder as ChangeRecorder, Subscriber},
+    node::{affects_descendants, DetachedNode, Node, NodeState, ParentAndIndex},
+    spatial::{PendingChanges, SpareIndex, SpatialIndex},
+    text::{
+        Delta as TextDelta, LiveRange as LiveTextRange, LiveRangeSet, Position as TextPosition,
+        Range as TextRange,
+    },
 };
 
+/// The state of a tree at a particular point in time.
+///
+/// The nodes are stored in a persistent map, so cloning the state is cheap,
+/// and a clone shares all of its unchanged nodes with the original.
 #[derive(Clone)]
 pub struct State {
-    pub(crate) nodes: HashMap<NodeId, NodeState>,
+    pub(crate) nodes: im::HashMap<NodeId, NodeState>,
     pub(crate) data: TreeData,
     pub(crate) focus: Option<NodeId>,
+    pub(crate) spatial_index: Option<Arc<SpatialIndex>>,
+    /// For each node, the nodes that list it in `labelled_by`, whose names
+    /// depend on it.
+    labelled_by_sources: im::HashMap<NodeId, im::HashSet<NodeId>>,
+    modal_nodes: im::HashSet<NodeId>,
+    modal: Option<NodeId>,
+    /// Counts the updates that led to this state.
+    pub(crate) generation: u64,
 }
 
 struct InternalFocusChange {
@@ -36,8 +54,174 @@ struct InternalFocusChange {
 struct InternalChanges {
     added_node_ids: HashSet<NodeId>,
     updated_nodes: HashMap<NodeId, DetachedNode>,
+    /// Maps each node that moved to a different parent to its old parent.
+    moved_node_ids: HashMap<NodeId, NodeId>,
+    reordered_parent_ids: HashSet<NodeId>,
+    old_root: Option<DetachedNode>,
+    old_tree_data: Option<TreeData>,
     focus_change: Option<InternalFocusChange>,
     removed_nodes: HashMap<NodeId, DetachedNode>,
+    /// The selection containers whose set of selected items changed.
+    selection_container_ids: HashSet<NodeId>,
+    /// The text deltas of documents whose text changed, in document order.
+    text_deltas: Vec<(NodeId, TextDelta)>,
+}
+
+/// Returns true if replacing `old` with `new` may change the text of
+/// a document that contains the node, or of the node itself.
+fn may_change_text(old: &NodeData, new: &NodeData) -> bool {
+    old.role != new.role
+        || old.value != new.value
+        || old.name != new.name
+        || old.character_lengths != new.character_lengths
+        || old.children != new.children
+}
+
+/// Returns the siblings of the modal node and of each of its ancestors.
+fn modal_boundary(modal: &Node) -> Vec<NodeId> {
+    let mut result = Vec::new();
+    let mut current = *modal;
+    while let Some(parent) = current.parent() {
+        result.extend(parent.child_ids().filter(|id| *id != current.id()));
+        current = parent;
+    }
+    result
+}
+
+/// Returns the selection container of the node with the given ID,
+/// if the node exists and is selected.
+fn selection_membership(state: &State, id: NodeId) -> Option<NodeId> {
+    let node = state.node_by_id(id)?;
+    if node.is_selected() != Some(true) {
+        return None;
+    }
+    node.selection_container().map(|container| container.id())
+}
+
+/// Returns true if the children that are in both lists aren't in the same
+/// order in both.
+fn are_children_reordered(old_children: &[NodeId], new_children: &[NodeId]) -> bool {
+    if old_children == new_children {
+        return false;
+    }
+    let old_set = old_children.iter().collect::<HashSet<_>>();
+    let new_set = new_children.iter().collect::<HashSet<_>>();
+    !old_children
+        .iter()
+        .filter(|id| new_set.contains(id))
+        .eq(new_children.iter().filter(|id| old_set.contains(id)))
+}
+
+fn add_label_sources(
+    sources: &mut im::HashMap<NodeId, im::HashSet<NodeId>>,
+    source: NodeId,
+    targets: &[NodeId],
+) {
+    for target in targets {
+        sources.entry(*target).or_default().insert(source);
+    }
+}
+
+fn remove_label_sources(
+    sources: &mut im::HashMap<NodeId, im::HashSet<NodeId>>,
+    source: NodeId,
+    targets: &[NodeId],
+) {
+    for target in targets {
+        if let Some(target_sources) = sources.get_mut(target) {
+            target_sources.remove(&source);
+            if target_sources.is_empty() {
+                sources.remove(target);
+            }
+        }
+    }
+}
+
+impl InternalChanges {
+    fn report(&self, state: &State, handler: &mut impl ChangeHandler) {
+        for id in &self.added_node_ids {
+            let node = state.node_by_id(*id).unwrap();
+            handler.node_added(&node);
+        }
+        for (id, old_node) in &self.updated_nodes {
+            let new_node = state.node_by_id(*id).unwrap();
+            handler.node_updated(old_node, &new_node);
+        }
+        for id in self.added_node_ids.iter().chain(self.updated_nodes.keys()) {
+            let node = state.node_by_id(*id).unwrap();
+            let old_annotations = self
+                .updated_nodes
+                .get(id)
+                .map_or(&[][..], |old_node| &old_node.data().text_annotations);
+            let new_annotations = &node.data().text_annotations;
+            for annotation in old_annotations {
+                if !new_annotations.contains(annotation) {
+                    handler.text_annotation_removed(&node, annotation);
+                }
+            }
+            for annotation in new_annotations {
+                if !old_annotations.contains(annotation) {
+                    handler.text_annotation_added(&node, annotation);
+                }
+            }
+        }
+        for (id, old_parent_id) in &self.moved_node_ids {
+            let old_parent = self
+                .updated_nodes
+                .get(old_parent_id)
+                .or_else(|| self.removed_nodes.get(old_parent_id));
+            let node = state.node_by_id(*id);
+            if let (Some(node), Some(old_parent)) = (node, old_parent) {
+                if let Some(new_parent) = node.parent() {
+                    handler.node_moved(&node, old_parent, &new_parent);
+                }
+            }
+        }
+        for id in &self.reordered_parent_ids {
+            if let Some(parent) = state.node_by_id(*id) {
+                handler.children_reordered(&parent);
+            }
+        }
+        if let Some(old_root) = &self.old_root {
+            handler.root_changed(old_root, &state.root());
+        }
+        if let Some(old_tree_data) = &self.old_tree_data {
+            handler.tree_data_changed(old_tree_data, &state.data);
+        }
+        if let Some(focus_change) = &self.focus_change {
+            if let Some(old_node) = &focus_change.old_focus {
+                let id = old_node.id();
+                if !self.updated_nodes.contains_key(&id) && !self.removed_nodes.contains_key(&id) {
+                    if let Some(old_node_new_version) = state.node_by_id(id) {
+                        handler.node_updated(old_node, &old_node_new_version);
+                    }
+                }
+            }
+            let new_node = state.focus();
+            if let Some(new_node) = new_node {
+                let id = new_node.id();
+                if !self.added_node_ids.contains(&id) && !self.updated_nodes.contains_key(&id) {
+                    if let Some(new_node_old_version) = &focus_change.new_focus_old_node {
+                        handler.node_updated(new_node_old_version, &new_node);
+                    }
+                }
+            }
+            handler.focus_moved(focus_change.old_focus.as_ref(), new_node.as_ref());
+        }
+        for node in self.removed_nodes.values() {
+            handler.node_removed(node, state);
+        }
+        for (id, delta) in &self.text_deltas {
+            if let Some(document) = state.node_by_id(*id) {
+                handler.text_changed(&document, delta);
+            }
+        }
+        for id in &self.selection_container_ids {
+            if let Some(container) = state.node_by_id(*id) {
+                handler.selection_changed(&container);
+            }
+        }
+    }
 }
 
 impl State {
@@ -51,7 +235,21 @@ impl State {
         }
     }
 
-    fn update(&mut self, update: TreeUpdate, mut changes: Option<&mut InternalChanges>) {
+    fn update(
+        &mut self,
+        update: TreeUpdate,
+        mut changes: Option<&mut InternalChanges>,
+    ) -> Option<PendingChanges> {
+        let mut spatial_changes = self
+            .spatial_index
+            .is_some()
+            .then(|| SpatialIndex::pending_changes(self, &update));
+
+        // If we're collecting changes, keep the old version of the state,
+        // so we can describe removed nodes as they were before the update.
+        // This is cheap, since the versions share all unchanged nodes.
+        let old_state = changes.is_some().then(|| self.clone());
+
         // First, if we're collecting changes, get the accurate state
         // of any updated nodes.
         if let Some(changes) = &mut changes {
@@ -64,13 +262,19 @@ impl State {
         }
 
         let mut orphans = HashSet::new();
-        let old_focus_id = self.focus;
-        let old_focus = self.focus.map(|id| self.node_by_id(id).unwrap().detached());
-        let old_root_id = self.data.root;
+        let old_focus_id = self.focus_id();
 
         if let Some(tree) = update.tree {
             if tree.root != self.data.root {
                 orphans.insert(self.data.root);
+                if let Some(changes) = &mut changes {
+                    changes.old_root = Some(self.root().detached());
+                }
+            }
+            if tree != self.data {
+                if let Some(changes) = &mut changes {
+                    changes.old_tree_data = Some(self.data.clone());
+                }
             }
             self.data = tree;
         }
@@ -78,9 +282,15 @@ impl State {
         let root = self.data.root;
         let mut pending_nodes: HashMap<NodeId, _> = HashMap::new();
         let mut pending_children = HashMap::new();
+        let mut touched_ids = Vec::new();
+        let mut replaced_data = HashMap::new();
+        // Nodes whose ancestors' transforms, roles or inherited properties
+        // may have changed, along with their own, which affects all of their
+        // descendants.
+        let mut stale_subtrees = Vec::new();
 
         fn add_node(
-            nodes: &mut HashMap<NodeId, NodeState>,
+            nodes: &mut im::HashMap<NodeId, NodeState>,
             changes: &mut Option<&mut InternalChanges>,
             parent_and_index: Option<ParentAndIndex>,
             id: NodeId,
@@ -90,6 +300,7 @@ impl State {
                 id,
                 parent_and_index,
                 data,
+                cache: Arc::default(),
             };
             nodes.insert(id, state);
             if let Some(changes) = changes {
@@ -99,6 +310,7 @@ impl State {
 
         for (node_id, node_data) in update.nodes {
             orphans.remove(&node_id);
+            touched_ids.push(node_id);
 
             let mut seen_child_ids = HashSet::new();
             for (child_index, child_id) in node_data.children.iter().enumerate() {
@@ -107,6 +319,14 @@ impl State {
                 let parent_and_index = ParentAndIndex(node_id, child_index);
                 if let Some(child_state) = self.nodes.get_mut(child_id) {
                     if child_state.parent_and_index != Some(parent_and_index) {
+                        if child_state.parent_id() != Some(node_id) {
+                            stale_subtrees.push(*child_id);
+                            if let (Some(changes), Some(old_parent_id)) =
+                                (&mut changes, child_state.parent_id())
+                            {
+                                changes.moved_node_ids.insert(*child_id, old_parent_id);
+                            }
+                        }
                         child_state.parent_and_index = Some(parent_and_index);
                     }
                 } else if let Some(child_data) = pending_nodes.remove(child_id) {
@@ -124,15 +344,25 @@ impl State {
             }
 
             if let Some(node_state) = self.nodes.get_mut(&node_id) {
-                if node_id == root {
-                    node_state.parent_and_index = None
+                if node_id == root && node_state.parent_and_index.is_some() {
+                    node_state.parent_and_index = None;
+                    stale_subtrees.push(node_id);
                 }
                 for child_id in node_state.data.children.iter() {
                     if !seen_child_ids.contains(child_id) {
                         orphans.insert(*child_id);
                     }
                 }
-                node_state.data = node_data;
+                if affects_descendants(&node_state.data, &node_data) {
+                    stale_subtrees.push(node_id);
+                }
+                let old_data = std::mem::replace(&mut node_state.data, node_data);
+                if let Some(changes) = &mut changes {
+                    if are_children_reordered(&old_data.children, &node_state.data.children) {
+                        changes.reordered_parent_ids.insert(node_id);
+                    }
+                }
+                replaced_data.insert(node_id, old_data);
             } else if let Some(parent_and_index) = pending_children.remove(&node_id) {
                 add_node(
                     &mut self.nodes,
@@ -151,74 +381,283 @@ impl State {
         assert_eq!(pending_nodes.len(), 0);
         assert_eq!(pending_children.len(), 0);
 
-        if update.focus != self.focus {
-            if let Some(changes) = &mut changes {
-                changes.focus_change = Some(InternalFocusChange {
-                    old_focus,
-                    new_focus_old_node: update
-                        .focus
-                        .and_then(|id| {
-                            (!changes.updated_nodes.contains_key(&id))
-                                .then(|| self.node_by_id(id).map(|node| node.detached()))
-                        })
-                        .flatten(),
-                });
+        for id in &touched_ids {
+            let new_targets = &self.nodes.get(id).unwrap().data.labelled_by;
+            if let Some(old_data) = replaced_data.get(id) {
+                if old_data.labelled_by == *new_targets {
+                    continue;
+                }
+                remove_label_sources(&mut self.labelled_by_sources, *id, &old_data.labelled_by);
             }
-            self.focus = update.focus;
+            add_label_sources(&mut self.labelled_by_sources, *id, new_targets);
         }
+        for id in &touched_ids {
+            if self.nodes.get(id).unwrap().data.modal {
+                self.modal_nodes.insert(*id);
+            } else {
+                self.modal_nodes.remove(id);
+            }
+        }
+
+        self.focus = update.focus;
 
+        let mut removed_ids = Vec::new();
         if !orphans.is_empty() {
             let mut to_remove = HashSet::new();
-
-            fn traverse_orphan(
-                nodes: &HashMap<NodeId, NodeState>,
-                to_remove: &mut HashSet<NodeId>,
-                id: NodeId,
-            ) {
+            // A node that an old parent dropped may have been adopted by
+            // another node elsewhere in the update, regardless of the order
+            // in which the update lists those nodes.
+            let mut pending = orphans
+                .into_iter()
+                .filter(|id| !self.is_attached(*id))
+                .collect::<Vec<NodeId>>();
+            while let Some(id) = pending.pop() {
                 to_remove.insert(id);
-                let node = nodes.get(&id).unwrap();
-                for child_id in node.data.children.iter() {
-                    traverse_orphan(nodes, to_remove, *child_id);
-                }
-            }
-
-            for id in orphans {
-                traverse_orphan(&self.nodes, &mut to_remove, id);
+                let node = self.nodes.get(&id).unwrap();
+                pending.extend(
+                    node.data.children.iter().copied().filter(|child_id| {
+                        self.nodes.get(child_id).unwrap().parent_id() == Some(id)
+                    }),
+                );
             }
 
             for id in to_remove {
+                if let Some(spatial_changes) = &mut spatial_changes {
+                    spatial_changes.removed.push(id);
+                }
                 if let Some(old_node_state) = self.nodes.remove(&id) {
+                    remove_label_sources(
+                        &mut self.labelled_by_sources,
+                        id,
+                        &old_node_state.data.labelled_by,
+                    );
+                    self.modal_nodes.remove(&id);
+                    removed_ids.push(id);
                     if let Some(changes) = &mut changes {
-                        let old_node = DetachedNode {
-                            state: old_node_state,
-                            is_focused: old_focus_id == Some(id),
-                            is_root: old_root_id == id,
-                            name: None,
-                            live: Live::Off,
-                            supports_text_ranges: false,
-                        };
-                        changes.removed_nodes.insert(id, old_node);
+                        let old_state = old_state.as_ref().unwrap();
+                        if let Some(old_node) = old_state.node_by_id(id) {
+                            changes.removed_nodes.insert(id, old_node.detached());
+                        }
+                    }
+                }
+            }
+        }
+
+        // The focus also moves when only the focused node's active descendant
+        // changes, so compare the effective focus once all nodes are in place.
+        let new_focus_id = self.focus_id();
+        if let (Some(changes), Some(old_state)) = (&mut changes, &old_state) {
+            if new_focus_id != old_focus_id {
+                changes.focus_change = Some(InternalFocusChange {
+                    old_focus: old_focus_id.map(|id| old_state.node_by_id(id).unwrap().detached()),
+                    new_focus_old_node: new_focus_id
+                        .filter(|id| !changes.updated_nodes.contains_key(id))
+                        .and_then(|id| old_state.node_by_id(id))
+                        .map(|node| node.detached()),
+                });
+            }
+        }
+
+        self.invalidate_caches(stale_subtrees, touched_ids, removed_ids);
+
+        if let (Some(changes), Some(old_state)) = (&mut changes, &old_state) {
+            let selection_container_ids = changes
+                .added_node_ids
+                .iter()
+                .chain(changes.updated_nodes.keys())
+                .chain(changes.moved_node_ids.keys())
+                .chain(changes.removed_nodes.keys())
+                .flat_map(|id| {
+                    let old = selection_membership(old_state, *id);
+                    let new = selection_membership(self, *id);
+                    (old != new).then(|| old.into_iter().chain(new))
+                })
+                .flatten()
+                .collect();
+            changes.selection_container_ids = selection_container_ids;
+
+            // Updates that don't change any text, such as scrolling, only
+            // change the bounds of boxes, so don't rebuild the text of
+            // their documents.
+            let mut document_ids = HashSet::new();
+            let text_changed_ids = changes
+                .updated_nodes
+                .iter()
+                .filter(|(id, old_node)| {
+                    let new_node = self.nodes.get(*id).unwrap();
+                    may_change_text(old_node.state().data(), new_node.data())
+                })
+                .map(|(id, _)| id);
+            for id in changes
+                .added_node_ids
+                .iter()
+                .chain(text_changed_ids)
+                .chain(changes.moved_node_ids.keys())
+                .chain(changes.removed_nodes.keys())
+            {
+                for state in [old_state, &*self] {
+                    if let Some(node) = state.node_by_id(*id) {
+                        if node.has_synthesized_text() {
+                            document_ids.insert(node.id());
+                        }
+                        if node.role() == Role::InlineTextBox
+                            || node.is_embedded_object()
+                            || node.has_synthesized_text()
+                        {
+                            document_ids.extend(node.text_document().map(|node| node.id()));
+                        }
+                    }
+                }
+            }
+            let mut documents = document_ids
+                .into_iter()
+                .filter_map(|id| Some((old_state.node_by_id(id)?, self.node_by_id(id)?)))
+                .collect::<Vec<_>>();
+            documents.sort_by_cached_key(|(_, new_document)| {
+                new_document.relative_index_path(self.data.root)
+            });
+            for (old_document, new_document) in documents {
+                let old_text = old_document.document_text();
+                let new_text = new_document.document_text();
+                if let Some(delta) = TextDelta::new(&old_text, &new_text) {
+                    changes.text_deltas.push((new_document.id(), delta));
+                }
+            }
+        }
+
+        let old_modal = self.modal;
+        self.modal = self.find_modal();
+        if self.modal != old_modal {
+            // Whether a node is hidden by the modal is cached, and a different
+            // modal may hide any node. Modals rarely open or close, so simply
+            // start over.
+            for (_, node) in self.nodes.iter_mut() {
+                node.cache = Arc::default();
+            }
+        }
+        if let (Some(changes), Some(old_state)) = (&mut changes, &old_state) {
+            if self.modal != old_modal {
+                // Only the nodes next to the path from the root to a modal
+                // change whether they're hidden by it; their descendants
+                // are hidden along with them.
+                let mut boundary = HashSet::new();
+                for (state, modal) in [(old_state, old_modal), (&*self, self.modal)] {
+                    if let Some(modal) = modal.and_then(|id| state.node_by_id(id)) {
+                        boundary.extend(modal_boundary(&modal));
+                    }
+                }
+                for id in boundary {
+                    if changes.added_node_ids.contains(&id)
+                        || changes.updated_nodes.contains_key(&id)
+                        || !self.has_node(id)
+                    {
+                        continue;
+                    }
+                    if let Some(old_node) = old_state.node_by_id(id) {
+                        changes.updated_nodes.insert(id, old_node.detached());
                     }
                 }
             }
         }
 
+        // Don't keep the old version's spatial index alive while updating it.
+        drop(old_state);
+
         self.validate_global();
+
+        if let Some(spatial_changes) = &spatial_changes {
+            let mut index = self.spatial_index.take().unwrap();
+            Arc::make_mut(&mut index).apply_changes(self, spatial_changes);
+            self.spatial_index = Some(index);
+        }
+        spatial_changes
     }
 
-    pub fn serialize(&self) -> TreeUpdate {
-        let mut nodes = Vec::new();
+    /// Picks the innermost visible modal node that contains the focus,
+    /// or if there isn't one, the last visible modal node in tree order.
+    fn find_modal(&self) -> Option<NodeId> {
+        if self.modal_nodes.is_empty() {
+            return None;
+        }
+        let candidates = self
+            .modal_nodes
+            .iter()
+            .map(|id| self.node_by_id(*id).unwrap())
+            .filter(|node| !node.is_hidden())
+            .map(|node| (node.index_path(), node))
+            .collect::<Vec<_>>();
+        let focus = self.focus();
+        candidates
+            .iter()
+            .filter(|(_, node)| matches!(&focus, Some(focus) if focus.is_descendant_of(node)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .or_else(|| candidates.iter().max_by(|(a, _), (b, _)| a.cmp(b)))
+            .map(|(_, node)| node.id())
+    }
 
-        fn traverse(state: &State, nodes: &mut Vec<(NodeId, Arc<NodeData>)>, id: NodeId) {
-            let node = state.nodes.get(&id).unwrap();
-            nodes.push((id, Arc::clone(&node.data)));
+    /// Returns true if the node is the root or is listed as a child
+    /// by its parent.
+    fn is_attached(&self, id: NodeId) -> bool {
+        match self.nodes.get(&id).unwrap().parent_and_index {
+            Some(ParentAndIndex(parent_id, index)) => matches!(
+                self.nodes.get(&parent_id),
+                Some(parent) if parent.data.children.get(index) == Some(&id)
+            ),
+            None => id == self.data.root,
+        }
+    }
+
+    /// Gives a fresh cache to every node whose cached values may differ
+    /// from those in the previous version of the state.
+    fn invalidate_caches(
+        &mut self,
+        stale_subtrees: Vec<NodeId>,
+        touched_ids: Vec<NodeId>,
+        removed_ids: Vec<NodeId>,
+    ) {
+        let mut stale = HashSet::new();
 
-            for child_id in node.data.children.iter() {
-                traverse(state, nodes, *child_id);
+        let mut pending = stale_subtrees;
+        while let Some(id) = pending.pop() {
+            if let Some(node) = self.nodes.get(&id) {
+                if stale.insert(id) {
+                    pending.extend(node.data.children.iter().copied());
+                }
+            }
+        }
+
+        // A node's name may come from its descendants or from the nodes
+        // it's labelled by, so it depends on any change to those.
+        let mut visited = HashSet::new();
+        let mut pending = touched_ids;
+        pending.extend(removed_ids);
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
             }
+            if let Some(node) = self.nodes.get(&id) {
+                stale.insert(id);
+                pending.extend(node.parent_id());
+            }
+            if let Some(sources) = self.labelled_by_sources.get(&id) {
+                pending.extend(sources.iter().copied());
+            }
+        }
+
+        for id in stale {
+            self.nodes.get_mut(&id).unwrap().cache = Arc::default();
         }
+    }
 
-        traverse(self, &mut nodes, self.data.root);
+    pub fn serialize(&self) -> TreeUpdate {
+        let mut nodes = Vec::new();
+        let mut pending = vec![self.data.root];
+        while let Some(id) = pending.pop() {
+            let node = self.nodes.get(&id).unwrap();
+            nodes.push((id, Arc::clone(&node.data)));
+            // Push the children in reverse, so they're serialized in order.
+            pending.extend(node.data.children.iter().rev().copied());
+        }
         assert_eq!(nodes.len(), self.nodes.len());
 
         TreeUpdate {
@@ -247,12 +686,47 @@ impl State {
         self.node_by_id(self.root_id()).unwrap()
     }
 
+    /// Returns the ID of the node that has the effective focus. When the node
+    /// that [`TreeUpdate::focus`] refers to has an active descendant,
+    /// the focus is delegated to that descendant, unless it isn't actually
+    /// a descendant or it's hidden.
     pub fn focus_id(&self) -> Option<NodeId> {
-        self.focus
+        let id = self.focus?;
+        let focused = self.node_by_id(id).unwrap();
+        let active_descendant = focused
+            .data()
+            .active_descendant
+            .and_then(|id| self.node_by_id(id))
+            .filter(|node| node.is_descendant_of(&focused) && !node.is_hidden());
+        Some(active_descendant.map_or(id, |node| node.id()))
     }
 
     pub fn focus(&self) -> Option<Node<'_>> {
-        self.focus.map(|id| self.node_by_id(id).unwrap())
+        self.focus_id().map(|id| self.node_by_id(id).unwrap())
+    }
+
+    pub fn modal_id(&self) -> Option<NodeId> {
+        self.modal
+    }
+
+    /// Returns the modal node that assistive technologies should be
+    /// confined to, if any. When there are several, this is the innermost
+    /// one that contains the focus, or else the last one in tree order.
+    pub fn modal(&self) -> Option<Node<'_>> {
+        self.modal.map(|id| self.node_by_id(id).unwrap())
+    }
+
+    /// Returns the node that [`TreeUpdate::focus`] refers to, if it has
+    /// delegated the focus to its active descendant.
+    pub fn active_descendant_owner(&self) -> Option<Node<'_>> {
+        let id = self.focus?;
+        (self.focus_id() != Some(id)).then(|| self.node_by_id(id).unwrap())
+    }
+
+    /// Returns whether hit testing uses a spatial index, rather than
+    /// visiting every node. See [`Tree::set_spatial_index_enabled`].
+    pub fn has_spatial_index(&self) -> bool {
+        self.spatial_index.is_some()
     }
 }
 
@@ -260,83 +734,191 @@ pub trait ChangeHandler {
     fn node_added(&mut self, node: &Node);
     fn node_updated(&mut self, old_node: &DetachedNode, new_node: &Node);
     fn focus_moved(&mut self, old_node: Option<&DetachedNode>, new_node: Option<&Node>);
-    /// The tree update process doesn't currently collect all possible information
-    /// about removed nodes. The following methods don't accurately reflect
-    /// the full state of the old node:
-    ///
-    /// * [`DetachedNode::name`]
-    /// * [`DetachedNode::live`]
-    /// * [`DetachedNode::supports_text_ranges`]
+    /// Called for a node that was already in the tree and now has
+    /// a different parent. This is in addition to
+    /// [`ChangeHandler::node_updated`], if the node itself was also updated.
+    /// `old_parent` reflects the state of the old parent before the update;
+    /// it may have been removed.
+    fn node_moved(&mut self, _node: &Node, _old_parent: &DetachedNode, _new_parent: &Node) {}
+    /// Called when the children that a node had both before and after
+    /// the update are in a different order. Added and removed children
+    /// alone don't count as a reordering.
+    fn children_reordered(&mut self, _parent: &Node) {}
+    fn root_changed(&mut self, _old_root: &DetachedNode, _new_root: &Node) {}
+    /// Called when the data that's global to the tree changed, including
+    /// when the root changed.
+    fn tree_data_changed(&mut self, _old_tree: &TreeData, _new_tree: &TreeData) {}
+    /// `node` reflects the full state of the node before the update,
+    /// including its old parent and its index within that parent's children.
     fn node_removed(&mut self, node: &DetachedNode, current_state: &State);
+    /// Called when the set of selected items in a selection container
+    /// changed, after the changes to the items themselves are reported.
+    fn selection_changed(&mut self, _container: &Node) {}
+    /// Called for each annotation that appeared on the text of a node,
+    /// including a node that was just added, after the node itself
+    /// is reported.
+    fn text_annotation_added(&mut self, _node: &Node, _annotation: &TextAnnotationData) {}
+    /// Called for each annotation that disappeared from the text of a node
+    /// that's still in the tree.
+    fn text_annotation_removed(&mut self, _node: &Node, _annotation: &TextAnnotationData) {}
+    /// Called when the text of a node that supports text ranges changed,
+    /// after the changes to its inline text boxes are reported.
+    fn text_changed(&mut self, _document: &Node, _delta: &TextDelta) {}
 }
 
 pub struct Tree {
-    state: RwLock<State>,
+    state: ArcSwap<State>,
+    /// Held while building and publishing a new version of the state,
+    /// so that updates are applied one at a time. Readers never take it.
+    writer: Mutex<SpareIndex>,
+    pub(crate) subscribers: Mutex<Vec<Subscriber>>,
+    pub(crate) live_text_ranges: LiveRangeSet,
     pub(crate) action_handler: Box<dyn ActionHandler>,
 }
 
+struct ReadGuard(Guard<Arc<State>>);
+
+impl Deref for ReadGuard {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        &self.0
+    }
+}
+
 impl Tree {
     pub fn new(mut initial_state: TreeUpdate, action_handler: Box<dyn ActionHandler>) -> Self {
         let mut state = State {
-            nodes: HashMap::new(),
+            nodes: im::HashMap::new(),
             data: initial_state.tree.take().unwrap(),
             focus: None,
+            spatial_index: None,
+            labelled_by_sources: im::HashMap::new(),
+            modal_nodes: im::HashSet::new(),
+            modal: None,
+            generation: 0,
         };
         state.update(initial_state, None);
         Self {
-            state: RwLock::new(state),
+            state: ArcSwap::from_pointee(state),
+            writer: Mutex::new(SpareIndex::default()),
+            subscribers: Mutex::new(Vec::new()),
+            live_text_ranges: LiveRangeSet::default(),
             action_handler,
         }
     }
 
+    fn publish_update(
+        &self,
+        spare_index: &mut SpareIndex,
+        update: TreeUpdate,
+        mut changes: Option<&mut InternalChanges>,
+    ) -> Arc<State> {
+        let mut state = State::clone(&self.state.load());
+        spare_index.prepare(&mut state);
+        let spatial_changes = state.update(update, changes.as_deref_mut());
+        spare_index.finish(spatial_changes);
+        state.generation += 1;
+        // Adjust the live ranges before publishing the state, so no reader
+        // can see the new text with offsets into the old text.
+        let deltas = changes.map_or_else(Vec::new, |changes| changes.text_deltas.clone());
+        self.live_text_ranges.apply_deltas(state.generation, deltas);
+        let state = Arc::new(state);
+        self.state.store(Arc::clone(&state));
+        state
+    }
+
     pub fn update(&self, update: TreeUpdate) {
-        let mut state = self.state.write();
-        state.update(update, None);
+        let mut spare_index = self.writer.lock();
+        // Text deltas are only computed along with the other changes,
+        // so skip them only if nothing needs any of them.
+        if self.subscribers.lock().is_empty() && self.live_text_ranges.is_empty() {
+            self.publish_update(&mut spare_index, update, None);
+            return;
+        }
+        let mut changes = InternalChanges::default();
+        let state = self.publish_update(&mut spare_index, update, Some(&mut changes));
+        self.notify_subscribers(&changes, &state);
     }
 
     pub fn update_and_process_changes(&self, update: TreeUpdate, handler: &mut impl ChangeHandler) {
         let mut changes = InternalChanges::default();
-        let mut state = self.state.write();
-        state.update(update, Some(&mut changes));
-        let state = RwLockWriteGuard::downgrade(state);
-        for id in &changes.added_node_ids {
-            let node = state.node_by_id(*id).unwrap();
-            handler.node_added(&node);
-        }
-        for (id, old_node) in &changes.updated_nodes {
-            let new_node = state.node_by_id(*id).unwrap();
-            handler.node_updated(old_node, &new_node);
-        }
-        if let Some(focus_change) = changes.focus_change {
-            if let Some(old_node) = &focus_change.old_focus {
-                let id = old_node.id();
-                if !changes.updated_nodes.contains_key(&id)
-                    && !changes.removed_nodes.contains_key(&id)
-                {
-                    if let Some(old_node_new_version) = state.node_by_id(id) {
-                        handler.node_updated(old_node, &old_node_new_version);
-                    }
-                }
-            }
-            let new_node = state.focus();
-            if let Some(new_node) = new_node {
-                let id = new_node.id();
-                if !changes.added_node_ids.contains(&id) && !changes.updated_nodes.contains_key(&id)
-                {
-                    if let Some(new_node_old_version) = focus_change.new_focus_old_node {
-                        handler.node_updated(&new_node_old_version, &new_node);
-                    }
-                }
-            }
-            handler.focus_moved(focus_change.old_focus.as_ref(), new_node.as_ref());
-        }
-        for node in changes.removed_nodes.values() {
-            handler.node_removed(node, &state);
+        // Keep other updates out until the handler has seen this one's changes,
+        // so that changes are always reported in the order they were applied.
+        let mut spare_index = self.writer.lock();
+        let state = self.publish_update(&mut spare_index, update, Some(&mut changes));
+        changes.report(&state, handler);
+        self.notify_subscribers(&changes, &state);
+    }
+
+    fn notify_subscribers(&self, changes: &InternalChanges, state: &Arc<State>) {
+        let mut subscribers = self.subscribers.lock();
+        if subscribers.is_empty() {
+            return;
         }
+        let mut recorder = ChangeRecorder::default();
+        changes.report(state, &mut recorder);
+        recorder.send(&mut subscribers, state);
     }
 
+    /// Returns a receiver for the changes made by all later updates,
+    /// including those applied with [`Tree::update`]. Each update's changes
+    /// are sent in the order they would be passed to a [`ChangeHandler`].
+    /// Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> mpsc::Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().push(Subscriber::Blocking(sender));
+        receiver
+    }
+
+    /// Like [`Tree::subscribe`], but the changes are delivered through
+    /// an async [`Stream`](futures_core::Stream).
+    pub fn subscribe_async(&self) -> EventStream {
+        let (sender, receiver) = async_channel::unbounded();
+        self.subscribers.lock().push(Subscriber::Async(sender));
+        EventStream(receiver)
+    }
+
+    /// Starts tracking `range` across updates. See [`LiveTextRange`]
+    /// for how edits to the document's text affect it.
+    pub fn track_text_range(&self, range: &TextRange) -> LiveTextRange {
+        self.live_text_ranges.track(range)
+    }
+
+    /// Returns the current state. This never blocks, even while an update
+    /// is being applied, and it doesn't block updates either; the returned
+    /// state simply isn't affected by them. Prefer [`Tree::snapshot`]
+    /// when holding on to the state for a long time.
     pub fn read(&self) -> impl Deref<Target = State> + '_ {
-        self.state.read()
+        ReadGuard(self.state.load())
+    }
+
+    /// Returns the current state, which remains unchanged by later updates.
+    /// Taking a snapshot is cheap; an update applied while a snapshot
+    /// is alive only copies what it changes.
+    pub fn snapshot(&self) -> Arc<State> {
+        self.state.load_full()
+    }
+
+    /// Enables or disables an R-tree of the nodes' transformed bounding boxes,
+    /// which is kept up to date as the tree is updated. With the index,
+    /// hit testing only has to check the nodes whose bounds contain
+    /// the point, rather than visiting every node, at the cost of some memory
+    /// and update time. The results are the same either way.
+    ///
+    /// Unlike the nodes, the index isn't a persistent data structure.
+    /// An update normally reuses the index of the version before
+    /// the current one, but if that version is still in use, e.g. by
+    /// a [snapshot](Tree::snapshot), the update has to copy the index.
+    pub fn set_spatial_index_enabled(&self, enabled: bool) {
+        let mut spare_index = self.writer.lock();
+        let mut state = State::clone(&self.state.load());
+        if enabled == state.spatial_index.is_some() {
+            return;
+        }
+        state.spatial_index = enabled.then(|| Arc::new(SpatialIndex::new(&state)));
+        *spare_index = SpareIndex::default();
+        self.state.store(Arc::new(state));
     }
 
     pub fn set_focus(&self, target: NodeId) {
@@ -407,6 +989,38 @@ impl Tree {
         })
     }
 
+    pub fn select(&self, target: NodeId) {
+        self.action_handler.do_action(ActionRequest {
+            action: Action::Select,
+            target,
+            data: None,
+        })
+    }
+
+    pub fn add_to_selection(&self, target: NodeId) {
+        self.action_handler.do_action(ActionRequest {
+            action: Action::AddToSelection,
+            target,
+            data: None,
+        })
+    }
+
+    pub fn remove_from_selection(&self, target: NodeId) {
+        self.action_handler.do_action(ActionRequest {
+            action: Action::RemoveFromSelection,
+            target,
+            data: None,
+        })
+    }
+
+    pub fn select_all(&self, target: NodeId) {
+        self.action_handler.do_action(ActionRequest {
+            action: Action::SelectAll,
+            target,
+            data: None,
+        })
+    }
+
     pub fn increment(&self, target: NodeId) {
         self.action_handler.do_action(ActionRequest {
             action: Action::Increment,
@@ -426,14 +1040,23 @@ impl Tree {
 
 #[cfg(test)]
 mod tests {
-    use accesskit::{Node, NodeId, Role, Tree, TreeUpdate};
-    use std::{num::NonZeroU128, sync::Arc};
+    use accesskit::kurbo::Affine;
+    use accesskit::{Live, Node, NodeId, Role, Tree, TreeUpdate};
+    use std::{
+        num::NonZeroU128,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
 
     use crate::tests::NullActionHandler;
 
     const NODE_ID_1: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(1) });
     const NODE_ID_2: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(2) });
     const NODE_ID_3: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(3) });
+    const NODE_ID_4: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(4) });
 
     #[test]
     fn init_tree_with_root_node() {
@@ -671,28 +1294,43 @@ mod tests {
     }
 
     #[test]
-    fn move_focus_between_siblings() {
+    fn removed_nodes_reflect_old_state() {
+        let root_node = Node {
+            role: Role::Window,
+            live: Some(Live::Polite),
+            transform: Some(Box::new(Affine::scale(2.0))),
+            ..Default::default()
+        };
         let first_update = TreeUpdate {
             nodes: vec![
                 (
                     NODE_ID_1,
                     Arc::new(Node {
-                        role: Role::Window,
-                        children: vec![NODE_ID_2, NODE_ID_3],
-                        ..Default::default()
+                        children: vec![NODE_ID_4, NODE_ID_2],
+                        ..root_node.clone()
                     }),
                 ),
                 (
                     NODE_ID_2,
                     Arc::new(Node {
                         role: Role::Button,
+                        children: vec![NODE_ID_3],
                         ..Default::default()
                     }),
                 ),
                 (
                     NODE_ID_3,
+                    Arc::new(Node {
+                        role: Role::StaticText,
+                        name: Some("foo".into()),
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_4,
                     Arc::new(Node {
                         role: Role::Button,
+                        name: Some("bar".into()),
                         ..Default::default()
                     }),
                 ),
@@ -701,32 +1339,114 @@ mod tests {
             focus: Some(NODE_ID_2),
         };
         let tree = super::Tree::new(first_update, Box::new(NullActionHandler {}));
-        assert!(tree.read().node_by_id(NODE_ID_2).unwrap().is_focused());
         let second_update = TreeUpdate {
-            nodes: vec![],
+            nodes: vec![(
+                NODE_ID_1,
+                Arc::new(Node {
+                    children: vec![NODE_ID_4],
+                    ..root_node
+                }),
+            )],
             tree: None,
-            focus: Some(NODE_ID_3),
+            focus: Some(NODE_ID_4),
         };
         struct Handler {
-            got_old_focus_node_update: bool,
-            got_new_focus_node_update: bool,
-            got_focus_change: bool,
-        }
-        fn unexpected_change() {
-            panic!("expected only focus change");
+            removed: Vec<crate::DetachedNode>,
         }
         impl super::ChangeHandler for Handler {
-            fn node_added(&mut self, _node: &crate::Node) {
-                unexpected_change();
+            fn node_added(&mut self, _node: &crate::Node) {}
+            fn node_updated(&mut self, _old_node: &crate::DetachedNode, _new_node: &crate::Node) {}
+            fn focus_moved(
+                &mut self,
+                _old_node: Option<&crate::DetachedNode>,
+                _new_node: Option<&crate::Node>,
+            ) {
             }
-            fn node_updated(&mut self, old_node: &crate::DetachedNode, new_node: &crate::Node) {
-                if old_node.id() == NODE_ID_2
-                    && new_node.id() == NODE_ID_2
-                    && old_node.is_focused()
-                    && !new_node.is_focused()
-                {
-                    self.got_old_focus_node_update = true;
-                    return;
+            fn node_removed(
+                &mut self,
+                node: &crate::DetachedNode,
+                _current_state: &crate::TreeState,
+            ) {
+                self.removed.push(node.clone());
+            }
+        }
+        let mut handler = Handler {
+            removed: Vec::new(),
+        };
+        tree.update_and_process_changes(second_update, &mut handler);
+        handler.removed.sort_by_key(|node| node.id().0);
+        assert_eq!(2, handler.removed.len());
+        let (button, text) = (&handler.removed[0], &handler.removed[1]);
+        assert_eq!(NODE_ID_2, button.id());
+        assert_eq!(Some(NODE_ID_1), button.parent_id());
+        assert_eq!(Some(1), button.index_in_parent());
+        assert!(button.is_focused());
+        assert_eq!(Some("foo".into()), button.name());
+        assert_eq!(Live::Polite, button.live());
+        assert_eq!(Affine::scale(2.0), button.transform());
+        assert_eq!(NODE_ID_3, text.id());
+        assert_eq!(Some(NODE_ID_2), text.parent_id());
+        assert_eq!(Some(0), text.index_in_parent());
+        assert_eq!(Live::Polite, text.live());
+    }
+
+    #[test]
+    fn move_focus_between_siblings() {
+        let first_update = TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![NODE_ID_2, NODE_ID_3],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_2,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_3,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: Some(NODE_ID_2),
+        };
+        let tree = super::Tree::new(first_update, Box::new(NullActionHandler {}));
+        assert!(tree.read().node_by_id(NODE_ID_2).unwrap().is_focused());
+        let second_update = TreeUpdate {
+            nodes: vec![],
+            tree: None,
+            focus: Some(NODE_ID_3),
+        };
+        struct Handler {
+            got_old_focus_node_update: bool,
+            got_new_focus_node_update: bool,
+            got_focus_change: bool,
+        }
+        fn unexpected_change() {
+            panic!("expected only focus change");
+        }
+        impl super::ChangeHandler for Handler {
+            fn node_added(&mut self, _node: &crate::Node) {
+                unexpected_change();
+            }
+            fn node_updated(&mut self, old_node: &crate::DetachedNode, new_node: &crate::Node) {
+                if old_node.id() == NODE_ID_2
+                    && new_node.id() == NODE_ID_2
+                    && old_node.is_focused()
+                    && !new_node.is_focused()
+                {
+                    self.got_old_focus_node_update = true;
+                    return;
                 }
                 if old_node.id() == NODE_ID_3
                     && new_node.id() == NODE_ID_3
@@ -772,6 +1492,239 @@ mod tests {
         assert!(!tree.read().node_by_id(NODE_ID_2).unwrap().is_focused());
     }
 
+    #[test]
+    fn modal_scoping() {
+        let dialog = |hidden| {
+            Arc::new(Node {
+                role: Role::Dialog,
+                children: vec![NODE_ID_4],
+                modal: true,
+                hidden,
+                ..Default::default()
+            })
+        };
+        let button = Arc::new(Node {
+            role: Role::Button,
+            ..Default::default()
+        });
+        let tree = super::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Window,
+                            children: vec![NODE_ID_2, NODE_ID_3],
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_2, button.clone()),
+                    (NODE_ID_3, dialog(false)),
+                    (NODE_ID_4, button),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: Some(NODE_ID_4),
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let state = tree.read();
+        assert_eq!(Some(NODE_ID_3), state.modal_id());
+        let hidden_by_modal = |id| state.node_by_id(id).unwrap().is_hidden_by_modal();
+        assert!(!hidden_by_modal(NODE_ID_1));
+        assert!(hidden_by_modal(NODE_ID_2));
+        assert!(!hidden_by_modal(NODE_ID_3));
+        assert!(!hidden_by_modal(NODE_ID_4));
+        drop(state);
+
+        // Hiding the dialog ends the modal scope, which is reported as
+        // an update to the node that it no longer hides.
+        let receiver = tree.subscribe();
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_3, dialog(true))],
+            tree: None,
+            focus: Some(NODE_ID_2),
+        });
+        assert!(tree.read().modal().is_none());
+        assert!(!tree
+            .read()
+            .node_by_id(NODE_ID_2)
+            .unwrap()
+            .is_hidden_by_modal());
+        assert!(receiver.try_iter().any(|event| matches!(
+            event.change,
+            crate::TreeChange::NodeUpdated { old_node, id }
+                if id == NODE_ID_2 && old_node.is_hidden_by_modal()
+        )));
+
+        // Whether a node is hidden by the modal is cached, so check that
+        // it follows the modal and the node moving.
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_3, dialog(false))],
+            tree: None,
+            focus: Some(NODE_ID_4),
+        });
+        let hidden_by_modal = |id| tree.read().node_by_id(id).unwrap().is_hidden_by_modal();
+        assert!(hidden_by_modal(NODE_ID_2));
+        assert!(!hidden_by_modal(NODE_ID_4));
+        tree.update(TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![NODE_ID_3],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_3,
+                    Arc::new(Node {
+                        role: Role::Dialog,
+                        children: vec![NODE_ID_4, NODE_ID_2],
+                        modal: true,
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: None,
+            focus: Some(NODE_ID_4),
+        });
+        assert_eq!(Some(NODE_ID_3), tree.read().modal_id());
+        assert!(!hidden_by_modal(NODE_ID_2));
+    }
+
+    #[test]
+    fn focus_follows_active_descendant() {
+        let list_box = |active_descendant| {
+            Arc::new(Node {
+                role: Role::ListBox,
+                children: vec![NODE_ID_2, NODE_ID_3],
+                active_descendant,
+                ..Default::default()
+            })
+        };
+        let option = Arc::new(Node {
+            role: Role::ListBoxOption,
+            ..Default::default()
+        });
+        let tree = super::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (NODE_ID_1, list_box(None)),
+                    (NODE_ID_2, option.clone()),
+                    (NODE_ID_3, option),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: Some(NODE_ID_1),
+            },
+            Box::new(NullActionHandler {}),
+        );
+        assert_eq!(Some(NODE_ID_1), tree.read().focus_id());
+        assert!(tree.read().active_descendant_owner().is_none());
+
+        let receiver = tree.subscribe();
+        let focus_moves = || {
+            receiver
+                .try_iter()
+                .filter_map(|event| match event.change {
+                    crate::TreeChange::FocusMoved {
+                        old_node,
+                        new_node_id,
+                    } => Some((old_node.map(|node| node.id()), new_node_id)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let set_active_descendant = |id| {
+            tree.update(TreeUpdate {
+                nodes: vec![(NODE_ID_1, list_box(id))],
+                tree: None,
+                focus: Some(NODE_ID_1),
+            })
+        };
+
+        set_active_descendant(Some(NODE_ID_2));
+        assert_eq!(focus_moves(), [(Some(NODE_ID_1), Some(NODE_ID_2))]);
+        let state = tree.read();
+        assert!(state.node_by_id(NODE_ID_2).unwrap().is_focused());
+        assert!(!state.root().is_focused());
+        assert_eq!(NODE_ID_1, state.active_descendant_owner().unwrap().id());
+        drop(state);
+
+        // Only the active descendant changes, but that still moves the focus.
+        set_active_descendant(Some(NODE_ID_3));
+        assert_eq!(focus_moves(), [(Some(NODE_ID_2), Some(NODE_ID_3))]);
+
+        // An active descendant that doesn't exist is ignored.
+        set_active_descendant(Some(NODE_ID_4));
+        assert_eq!(focus_moves(), [(Some(NODE_ID_3), Some(NODE_ID_1))]);
+        set_active_descendant(None);
+        assert!(focus_moves().is_empty());
+    }
+
+    #[test]
+    fn active_descendant_must_be_a_visible_descendant() {
+        let list_box = |active_descendant| {
+            Arc::new(Node {
+                role: Role::ListBox,
+                children: vec![NODE_ID_3],
+                active_descendant,
+                ..Default::default()
+            })
+        };
+        let option = |hidden| {
+            Arc::new(Node {
+                role: Role::ListBoxOption,
+                hidden,
+                ..Default::default()
+            })
+        };
+        let tree = super::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Window,
+                            children: vec![NODE_ID_2, NODE_ID_4],
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_2, list_box(Some(NODE_ID_4))),
+                    (NODE_ID_3, option(true)),
+                    (
+                        NODE_ID_4,
+                        Arc::new(Node {
+                            role: Role::Button,
+                            ..Default::default()
+                        }),
+                    ),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: Some(NODE_ID_2),
+            },
+            Box::new(NullActionHandler {}),
+        );
+        // The button isn't inside the list box.
+        assert_eq!(Some(NODE_ID_2), tree.read().focus_id());
+        assert!(tree.read().active_descendant_owner().is_none());
+
+        // The option is hidden.
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_2, list_box(Some(NODE_ID_3)))],
+            tree: None,
+            focus: Some(NODE_ID_2),
+        });
+        assert_eq!(Some(NODE_ID_2), tree.read().focus_id());
+
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_3, option(false))],
+            tree: None,
+            focus: Some(NODE_ID_2),
+        });
+        assert_eq!(Some(NODE_ID_3), tree.read().focus_id());
+    }
+
     #[test]
     fn update_node() {
         let child_node = Node {
@@ -860,4 +1813,448 @@ mod tests {
             tree.read().node_by_id(NODE_ID_2).unwrap().name()
         );
     }
+
+    #[test]
+    fn snapshot_is_unaffected_by_later_updates() {
+        let update = TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![NODE_ID_2],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_2,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        name: Some("foo".into()),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: None,
+        };
+        let tree = super::Tree::new(update, Box::new(NullActionHandler {}));
+        let snapshot = tree.snapshot();
+        tree.update(TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![NODE_ID_3],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_3,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        name: Some("bar".into()),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: None,
+            focus: Some(NODE_ID_3),
+        });
+        assert_eq!(
+            Some("foo".into()),
+            snapshot.node_by_id(NODE_ID_2).unwrap().name()
+        );
+        assert!(!snapshot.has_node(NODE_ID_3));
+        assert!(snapshot.focus().is_none());
+        let state = tree.read();
+        assert!(!state.has_node(NODE_ID_2));
+        assert_eq!(
+            Some("bar".into()),
+            state.node_by_id(NODE_ID_3).unwrap().name()
+        );
+        assert_eq!(NODE_ID_3, state.focus().unwrap().id());
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        // Every update replaces the root's only child with a new, focused node
+        // named after its ID, so readers can check that they never see
+        // a partially applied update.
+        const WRITERS: u128 = 2;
+        const READERS: usize = 4;
+        const UPDATES_PER_WRITER: u128 = 500;
+
+        fn update_for_child(id: NodeId) -> TreeUpdate {
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Window,
+                            children: vec![id],
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        id,
+                        Arc::new(Node {
+                            role: Role::Button,
+                            name: Some(id.0.to_string().into()),
+                            ..Default::default()
+                        }),
+                    ),
+                ],
+                tree: None,
+                focus: Some(id),
+            }
+        }
+
+        fn check_state(state: &crate::TreeState) {
+            let root = state.root();
+            assert_eq!(1, root.children().count());
+            let child = root.children().next().unwrap();
+            assert_eq!(Some(child.id().0.to_string()), child.name());
+            assert_eq!(child.id(), state.focus().unwrap().id());
+            assert_eq!(2, state.nodes.len());
+        }
+
+        struct Handler {
+            added: u128,
+        }
+        impl super::ChangeHandler for Handler {
+            fn node_added(&mut self, node: &crate::Node) {
+                assert!(node.is_focused());
+                self.added += 1;
+            }
+            fn node_updated(&mut self, _old_node: &crate::DetachedNode, _new_node: &crate::Node) {}
+            fn focus_moved(
+                &mut self,
+                _old_node: Option<&crate::DetachedNode>,
+                _new_node: Option<&crate::Node>,
+            ) {
+            }
+            fn node_removed(
+                &mut self,
+                _node: &crate::DetachedNode,
+                _current_state: &crate::TreeState,
+            ) {
+            }
+        }
+
+        let mut initial_state = update_for_child(NODE_ID_2);
+        initial_state.tree = Some(Tree::new(NODE_ID_1));
+        let tree = Arc::new(super::Tree::new(
+            initial_state,
+            Box::new(NullActionHandler {}),
+        ));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0..READERS)
+            .map(|_| {
+                let tree = Arc::clone(&tree);
+                let done = Arc::clone(&done);
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    while !done.load(Ordering::Relaxed) || reads == 0 {
+                        check_state(&tree.read());
+                        let snapshot = tree.snapshot();
+                        check_state(&snapshot);
+                        reads += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let writers = (0..WRITERS)
+            .map(|writer| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    let mut handler = Handler { added: 0 };
+                    for i in 0..UPDATES_PER_WRITER {
+                        let id = NonZeroU128::new(10 + writer + WRITERS * i).unwrap();
+                        let update = update_for_child(NodeId(id));
+                        if writer == 0 {
+                            tree.update(update);
+                        } else {
+                            tree.update_and_process_changes(update, &mut handler);
+                        }
+                    }
+                    handler.added
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let added = writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap())
+            .sum::<u128>();
+        assert_eq!(UPDATES_PER_WRITER, added);
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        check_state(&tree.read());
+    }
+
+    #[test]
+    fn deep_tree() {
+        const DEPTH: u128 = 50_000;
+        let tree = crate::tests::deep_tree(DEPTH);
+        let serialized = tree.read().serialize();
+        assert!(serialized
+            .nodes
+            .iter()
+            .map(|(id, _)| id.0.get())
+            .eq(1..=DEPTH + 1));
+        // Detaching the chain from the root removes all of its nodes.
+        tree.update(TreeUpdate {
+            nodes: vec![(
+                NODE_ID_1,
+                Arc::new(Node {
+                    role: Role::Window,
+                    ..Default::default()
+                }),
+            )],
+            tree: None,
+            focus: None,
+        });
+        assert_eq!(1, tree.read().nodes.len());
+    }
+
+    #[derive(Default)]
+    struct StructuralChangeRecorder {
+        moved: Vec<(NodeId, NodeId, NodeId)>,
+        reordered: Vec<NodeId>,
+        root_changes: Vec<(NodeId, NodeId)>,
+        tree_data_changes: Vec<(Tree, Tree)>,
+    }
+
+    impl super::ChangeHandler for StructuralChangeRecorder {
+        fn node_added(&mut self, _node: &crate::Node) {}
+        fn node_updated(&mut self, _old_node: &crate::DetachedNode, _new_node: &crate::Node) {}
+        fn node_moved(
+            &mut self,
+            node: &crate::Node,
+            old_parent: &crate::DetachedNode,
+            new_parent: &crate::Node,
+        ) {
+            assert!(old_parent.data().children.contains(&node.id()));
+            self.moved
+                .push((node.id(), old_parent.id(), new_parent.id()));
+        }
+        fn children_reordered(&mut self, parent: &crate::Node) {
+            self.reordered.push(parent.id());
+        }
+        fn root_changed(&mut self, old_root: &crate::DetachedNode, new_root: &crate::Node) {
+            assert!(old_root.is_root());
+            self.root_changes.push((old_root.id(), new_root.id()));
+        }
+        fn tree_data_changed(&mut self, old_tree: &Tree, new_tree: &Tree) {
+            self.tree_data_changes
+                .push((old_tree.clone(), new_tree.clone()));
+        }
+        fn focus_moved(
+            &mut self,
+            _old_node: Option<&crate::DetachedNode>,
+            _new_node: Option<&crate::Node>,
+        ) {
+        }
+        fn node_removed(&mut self, _node: &crate::DetachedNode, _current_state: &crate::TreeState) {
+        }
+    }
+
+    #[test]
+    fn move_and_reorder_children() {
+        let node = |children: Vec<NodeId>| {
+            Arc::new(Node {
+                role: Role::Group,
+                children,
+                ..Default::default()
+            })
+        };
+        let first_update = TreeUpdate {
+            nodes: vec![
+                (NODE_ID_1, node(vec![NODE_ID_2, NODE_ID_3])),
+                (NODE_ID_2, node(vec![NODE_ID_4])),
+                (NODE_ID_3, node(vec![])),
+                (NODE_ID_4, node(vec![])),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: None,
+        };
+        let tree = super::Tree::new(first_update, Box::new(NullActionHandler {}));
+        let second_update = TreeUpdate {
+            // The new parent comes before the old one, which still has to
+            // be treated as a move rather than a removal.
+            nodes: vec![
+                (NODE_ID_3, node(vec![NODE_ID_4])),
+                (NODE_ID_2, node(vec![])),
+                (NODE_ID_1, node(vec![NODE_ID_3, NODE_ID_2])),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: None,
+        };
+        let mut recorder = StructuralChangeRecorder::default();
+        tree.update_and_process_changes(second_update, &mut recorder);
+        assert_eq!(vec![(NODE_ID_4, NODE_ID_2, NODE_ID_3)], recorder.moved);
+        assert_eq!(vec![NODE_ID_1], recorder.reordered);
+        assert!(recorder.root_changes.is_empty());
+        assert!(recorder.tree_data_changes.is_empty());
+        assert!(tree.read().has_node(NODE_ID_4));
+
+        // Adding a child in front of the others isn't a reordering.
+        let third_update = TreeUpdate {
+            nodes: vec![
+                (NODE_ID_3, node(vec![])),
+                (NODE_ID_1, node(vec![NODE_ID_4, NODE_ID_3, NODE_ID_2])),
+            ],
+            tree: None,
+            focus: None,
+        };
+        let mut recorder = StructuralChangeRecorder::default();
+        tree.update_and_process_changes(third_update, &mut recorder);
+        assert_eq!(vec![(NODE_ID_4, NODE_ID_3, NODE_ID_1)], recorder.moved);
+        assert!(recorder.reordered.is_empty());
+    }
+
+    #[test]
+    fn change_root() {
+        let first_update = TreeUpdate {
+            nodes: vec![
+                (
+                    NODE_ID_1,
+                    Arc::new(Node {
+                        role: Role::Window,
+                        children: vec![NODE_ID_2],
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    NODE_ID_2,
+                    Arc::new(Node {
+                        role: Role::Button,
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            tree: Some(Tree::new(NODE_ID_1)),
+            focus: None,
+        };
+        let tree = super::Tree::new(first_update, Box::new(NullActionHandler {}));
+        let second_update = TreeUpdate {
+            nodes: vec![(
+                NODE_ID_3,
+                Arc::new(Node {
+                    role: Role::Window,
+                    children: vec![NODE_ID_2],
+                    ..Default::default()
+                }),
+            )],
+            tree: Some(Tree::new(NODE_ID_3)),
+            focus: None,
+        };
+        let mut recorder = StructuralChangeRecorder::default();
+        tree.update_and_process_changes(second_update, &mut recorder);
+        assert_eq!(vec![(NODE_ID_2, NODE_ID_1, NODE_ID_3)], recorder.moved);
+        assert_eq!(vec![(NODE_ID_1, NODE_ID_3)], recorder.root_changes);
+        assert_eq!(
+            vec![(Tree::new(NODE_ID_1), Tree::new(NODE_ID_3))],
+            recorder.tree_data_changes
+        );
+        assert!(!tree.read().has_node(NODE_ID_1));
+    }
+
+    #[test]
+    fn selection_changes() {
+        let list_box = |children| {
+            Arc::new(Node {
+                role: Role::ListBox,
+                children,
+                multiselectable: true,
+                ..Default::default()
+            })
+        };
+        let option = |name: &str, selected| {
+            Arc::new(Node {
+                role: Role::ListBoxOption,
+                name: Some(name.into()),
+                selected: Some(selected),
+                ..Default::default()
+            })
+        };
+        let tree = super::Tree::new(
+            TreeUpdate {
+                nodes: vec![
+                    (
+                        NODE_ID_1,
+                        Arc::new(Node {
+                            role: Role::Window,
+                            children: vec![NODE_ID_2],
+                            ..Default::default()
+                        }),
+                    ),
+                    (NODE_ID_2, list_box(vec![NODE_ID_3, NODE_ID_4])),
+                    (NODE_ID_3, option("a", true)),
+                    (NODE_ID_4, option("b", false)),
+                ],
+                tree: Some(Tree::new(NODE_ID_1)),
+                focus: None,
+            },
+            Box::new(NullActionHandler {}),
+        );
+        let selected_ids = || {
+            let state = tree.read();
+            let list_box = state.node_by_id(NODE_ID_2).unwrap();
+            list_box
+                .selected_children()
+                .map(|node| node.id())
+                .collect::<Vec<_>>()
+        };
+        let selection_changed = |receiver: &std::sync::mpsc::Receiver<crate::TreeEvent>| {
+            receiver.try_iter().any(|event| {
+                matches!(event.change, crate::TreeChange::SelectionChanged(id) if id == NODE_ID_2)
+            })
+        };
+        assert_eq!(
+            Some(NODE_ID_2),
+            tree.read()
+                .node_by_id(NODE_ID_4)
+                .unwrap()
+                .selection_container()
+                .map(|node| node.id())
+        );
+        assert_eq!(vec![NODE_ID_3], selected_ids());
+
+        let receiver = tree.subscribe();
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_4, option("b", true))],
+            tree: None,
+            focus: None,
+        });
+        assert!(selection_changed(&receiver));
+        assert_eq!(vec![NODE_ID_3, NODE_ID_4], selected_ids());
+        assert_eq!(
+            2,
+            tree.read().node_by_id(NODE_ID_2).unwrap().selected_count()
+        );
+
+        // Changing a selected item without deselecting it doesn't change
+        // the selection.
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_3, option("c", true))],
+            tree: None,
+            focus: None,
+        });
+        assert!(!selection_changed(&receiver));
+
+        // Removing a selected item does.
+        tree.update(TreeUpdate {
+            nodes: vec![(NODE_ID_2, list_box(vec![NODE_ID_3]))],
+            tree: None,
+            focus: None,
+        });
+        assert!(selection_changed(&receiver));
+        assert_eq!(vec![NODE_ID_3], selected_ids());
+    }
 }
@@ -11,7 +11,9 @@
 #![allow(non_upper_case_globals)]
 
 use accesskit::{CheckedState, NodeId, Role, TextSelection};
-use accesskit_consumer::{DetachedNode, FilterResult, Node, NodeState, Tree};
+use accesskit_consumer::{
+    DetachedNode, FilterPolicy, FilterPolicyBuilder, FilterResult, Node, NodeState, Tree,
+};
 use objc2::{
     declare::{Ivar, IvarDrop},
     declare_class,
@@ -231,33 +233,20 @@ fn ns_role(node_state: &NodeState) -> &'static NSString {
     }
 }
 
-fn filter_common(node_state: &NodeState) -> FilterResult {
-    let ns_role = ns_role(node_state);
-    if ns_role == unsafe { NSAccessibilityUnknownRole } {
-        return FilterResult::ExcludeNode;
-    }
-
-    if node_state.is_hidden() {
-        return FilterResult::ExcludeSubtree;
-    }
-
-    FilterResult::Include
+fn has_unknown_role(node_state: &NodeState) -> bool {
+    ns_role(node_state) == unsafe { NSAccessibilityUnknownRole }
 }
 
-pub(crate) fn filter(node: &Node) -> FilterResult {
-    if node.is_focused() {
-        return FilterResult::Include;
-    }
+const FILTER_POLICY: FilterPolicy = FilterPolicyBuilder::new()
+    .exclude_node_if(has_unknown_role)
+    .build();
 
-    filter_common(node.state())
+pub(crate) fn filter(node: &Node) -> FilterResult {
+    FILTER_POLICY.filter(node)
 }
 
 pub(crate) fn filter_detached(node: &DetachedNode) -> FilterResult {
-    if node.is_focused() {
-        return FilterResult::Include;
-    }
-
-    filter_common(node.state())
+    FILTER_POLICY.filter_detached(node)
 }
 
 pub(crate) fn can_be_focused(node: &Node) -> bool {
@@ -75,15 +75,12 @@ impl Adapter {
         let reader = self.tree.read();
         let mut objects_to_add = Vec::new();
 
-        fn add_children(node: Node<'_>, to_add: &mut Vec<NodeId>) {
-            for child in node.filtered_children(&filter) {
-                to_add.push(child.id());
-                add_children(child, to_add);
-            }
+        let mut pending = vec![reader.root()];
+        while let Some(node) = pending.pop() {
+            objects_to_add.push(node.id());
+            // Push the children in reverse, so they're registered in order.
+            pending.extend(node.filtered_children(&filter).rev());
         }
-
-        objects_to_add.push(reader.root().id());
-        add_children(reader.root(), &mut objects_to_add);
         for id in objects_to_add {
             let interfaces = NodeWrapper::Node(&reader.node_by_id(id).unwrap()).interfaces();
             self.register_interfaces(&self.tree, id, interfaces)
@@ -149,6 +146,13 @@ impl Adapter {
         Ok(true)
     }
 
+    /// Enables or disables the spatial index used to answer hit tests,
+    /// such as `GetAccessibleAtPoint` requests, without visiting every node.
+    /// This is worthwhile for large trees.
+    pub fn set_spatial_index_enabled(&self, enabled: bool) {
+        self.tree.set_spatial_index_enabled(enabled);
+    }
+
     pub fn set_root_window_bounds(&self, outer: Rect, inner: Rect) {
         let mut bounds = self.root_window_bounds.write();
         bounds.outer = outer;
@@ -234,11 +238,20 @@ impl Adapter {
                         );
                     }
                 }
-                if let Some(node) = new_node.map(NodeWrapper::Node) {
+                if let Some(node) = new_node {
+                    if let Some(owner) = node.tree_state.active_descendant_owner() {
+                        self.adapter
+                            .events
+                            .send_blocking(Event::Object {
+                                target: NodeWrapper::Node(&owner).id(),
+                                event: ObjectEvent::ActiveDescendantChanged(node.id().into()),
+                            })
+                            .unwrap();
+                    }
                     self.adapter
                         .events
                         .send_blocking(Event::Object {
-                            target: node.id(),
+                            target: NodeWrapper::Node(node).id(),
                             event: ObjectEvent::StateChanged(State::Focused, true),
                         })
                         .unwrap();
@@ -258,6 +271,17 @@ impl Adapter {
                     self.remove_node(node);
                 }
             }
+            fn selection_changed(&mut self, container: &Node) {
+                if filter(container) == FilterResult::Include {
+                    self.adapter
+                        .events
+                        .send_blocking(Event::Object {
+                            target: NodeWrapper::Node(container).id(),
+                            event: ObjectEvent::SelectionChanged,
+                        })
+                        .unwrap();
+                }
+            }
         }
         let mut handler = Handler {
             adapter: self,
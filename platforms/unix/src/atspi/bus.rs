@@ -83,13 +83,30 @@ impl Bus {
     ) -> Result<()> {
         let interface = "org.a11y.atspi.Event.Object";
         let signal = match event {
+            ObjectEvent::ActiveDescendantChanged(_) => "ActiveDescendantChanged",
             ObjectEvent::BoundsChanged(_) => "BoundsChanged",
             ObjectEvent::ChildAdded(_, _) | ObjectEvent::ChildRemoved(_) => "ChildrenChanged",
             ObjectEvent::PropertyChanged(_) => "PropertyChange",
+            ObjectEvent::SelectionChanged => "SelectionChanged",
             ObjectEvent::StateChanged(_, _) => "StateChanged",
         };
         let properties = HashMap::new();
         match event {
+            ObjectEvent::ActiveDescendantChanged(child) => {
+                self.emit_event(
+                    target,
+                    interface,
+                    signal,
+                    EventBody {
+                        kind: "",
+                        detail1: 0,
+                        detail2: 0,
+                        any_data: child.into_value(self.unique_name().clone()),
+                        properties,
+                    },
+                )
+                .await
+            }
             ObjectEvent::BoundsChanged(bounds) => {
                 self.emit_event(
                     target,
@@ -135,6 +152,21 @@ impl Bus {
                 )
                 .await
             }
+            ObjectEvent::SelectionChanged => {
+                self.emit_event(
+                    target,
+                    interface,
+                    signal,
+                    EventBody {
+                        kind: "",
+                        detail1: 0,
+                        detail2: 0,
+                        any_data: 0i32.into(),
+                        properties,
+                    },
+                )
+                .await
+            }
             ObjectEvent::PropertyChanged(property) => {
                 self.emit_event(
                     target,
@@ -8,7 +8,7 @@ use crate::{
     unknown_object, PlatformNode, PlatformRootNode,
 };
 use atspi::{accessible::Role, Interface, InterfaceSet, StateSet};
-use std::convert::TryInto;
+use std::{collections::HashMap, convert::TryInto};
 use zbus::{fdo, names::OwnedUniqueName, MessageHeader};
 
 pub(crate) struct AccessibleInterface<T> {
@@ -93,6 +93,10 @@ impl AccessibleInterface<PlatformNode> {
         self.node.state()
     }
 
+    fn get_attributes(&self) -> fdo::Result<HashMap<String, String>> {
+        self.node.attributes()
+    }
+
     fn get_application(
         &self,
         #[zbus(header)] hdr: MessageHeader<'_>,
@@ -192,6 +196,10 @@ impl AccessibleInterface<PlatformRootNode> {
         StateSet::empty()
     }
 
+    fn get_attributes(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
     fn get_application(
         &self,
         #[zbus(header)] hdr: MessageHeader<'_>,
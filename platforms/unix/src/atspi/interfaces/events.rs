@@ -28,10 +28,12 @@ pub(crate) enum Property {
 
 #[allow(clippy::enum_variant_names)]
 pub(crate) enum ObjectEvent {
+    ActiveDescendantChanged(ObjectRef),
     BoundsChanged(Rect),
     ChildAdded(usize, ObjectRef),
     ChildRemoved(ObjectRef),
     PropertyChanged(Property),
+    SelectionChanged,
     StateChanged(State, bool),
 }
 
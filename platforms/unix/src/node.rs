@@ -19,7 +19,9 @@ use accesskit::{
     kurbo::{Affine, Point, Rect},
     CheckedState, DefaultActionVerb, NodeId, Role,
 };
-use accesskit_consumer::{DetachedNode, FilterResult, Node, NodeState, Tree, TreeState};
+use accesskit_consumer::{
+    DetachedNode, FilterPolicy, FilterPolicyBuilder, FilterResult, Node, NodeState, Tree, TreeState,
+};
 use async_channel::Sender;
 use atspi::{
     accessible::Role as AtspiRole, component::Layer, CoordType, Interface, InterfaceSet, State,
@@ -27,38 +29,20 @@ use atspi::{
 };
 use parking_lot::RwLock;
 use std::{
+    collections::HashMap,
     iter::FusedIterator,
     sync::{Arc, Weak},
 };
 use zbus::fdo;
 
-fn filter_common(node: &NodeState) -> FilterResult {
-    if node.is_hidden() {
-        return FilterResult::ExcludeSubtree;
-    }
-
-    let role = node.role();
-    if role == Role::Presentation || role == Role::GenericContainer || role == Role::InlineTextBox {
-        return FilterResult::ExcludeNode;
-    }
-
-    FilterResult::Include
-}
+const FILTER_POLICY: FilterPolicy = FilterPolicyBuilder::new().hide_outside_modal(true).build();
 
 pub(crate) fn filter(node: &Node) -> FilterResult {
-    if node.is_focused() {
-        return FilterResult::Include;
-    }
-
-    filter_common(node.state())
+    FILTER_POLICY.filter(node)
 }
 
 pub(crate) fn filter_detached(node: &DetachedNode) -> FilterResult {
-    if node.is_focused() {
-        return FilterResult::Include;
-    }
-
-    filter_common(node.state())
+    FILTER_POLICY.filter_detached(node)
 }
 
 pub(crate) enum NodeWrapper<'a> {
@@ -336,6 +320,20 @@ impl<'a> NodeWrapper<'a> {
         }
     }
 
+    fn is_disabled(&self) -> bool {
+        match self {
+            Self::Node(node) => node.is_disabled(),
+            Self::DetachedNode(node) => node.is_disabled(),
+        }
+    }
+
+    fn is_read_only_or_disabled(&self) -> bool {
+        match self {
+            Self::Node(node) => node.is_read_only_or_disabled(),
+            Self::DetachedNode(node) => node.is_read_only_or_disabled(),
+        }
+    }
+
     pub fn state(&self) -> StateSet {
         let state = self.node_state();
         let atspi_role = self.role();
@@ -358,13 +356,16 @@ impl<'a> NodeWrapper<'a> {
             atspi_state.insert(State::Checkable);
         }
         if let Some(selected) = state.is_selected() {
-            if !state.is_disabled() {
+            if !self.is_disabled() {
                 atspi_state.insert(State::Selectable);
             }
             if selected {
                 atspi_state.insert(State::Selected);
             }
         }
+        if state.is_modal() {
+            atspi_state.insert(State::Modal);
+        }
         if state.is_text_field() {
             atspi_state.insert(State::SelectableText);
             atspi_state.insert(match state.is_multiline() {
@@ -388,7 +389,7 @@ impl<'a> NodeWrapper<'a> {
             _ => {}
         }
 
-        if state.is_read_only_supported() && state.is_read_only_or_disabled() {
+        if state.is_read_only_supported() && self.is_read_only_or_disabled() {
             atspi_state.insert(State::ReadOnly);
         } else {
             atspi_state.insert(State::Enabled | State::Sensitive);
@@ -626,7 +627,7 @@ impl PlatformNode {
         F: FnOnce(&TreeState) -> fdo::Result<T>,
     {
         let tree = self.upgrade_tree()?;
-        let state = tree.read();
+        let state = tree.snapshot();
         f(&state)
     }
 
@@ -736,6 +737,22 @@ impl PlatformNode {
         })
     }
 
+    pub fn attributes(&self) -> fdo::Result<HashMap<String, String>> {
+        self.resolve(|node| {
+            let mut attributes = HashMap::new();
+            if let Some(pos_in_set) = node.pos_in_set() {
+                attributes.insert("posinset".into(), pos_in_set.to_string());
+            }
+            if let Some(set_size) = node.set_size() {
+                attributes.insert("setsize".into(), set_size.to_string());
+            }
+            if let Some(level) = node.hierarchical_level() {
+                attributes.insert("level".into(), level.to_string());
+            }
+            Ok(attributes)
+        })
+    }
+
     pub fn n_actions(&self) -> fdo::Result<i32> {
         self.resolve(|node| {
             let wrapper = NodeWrapper::Node(&node);
@@ -3,10 +3,12 @@
 // the LICENSE-APACHE file) or the MIT license (found in
 // the LICENSE-MIT file), at your option.
 
-use std::{collections::HashSet, sync::Arc};
+use std::sync::Arc;
 
-use accesskit::{ActionHandler, Live, NodeId, Role, TreeUpdate};
-use accesskit_consumer::{DetachedNode, FilterResult, Node, Tree, TreeChangeHandler, TreeState};
+use accesskit::{ActionHandler, Live, TreeUpdate};
+use accesskit_consumer::{
+    DetachedNode, FilterResult, Node, TextDelta, Tree, TreeChangeHandler, TreeState,
+};
 use windows::Win32::{
     Foundation::*,
     UI::{Accessibility::*, WindowsAndMessaging::*},
@@ -48,57 +50,9 @@ impl Adapter {
             tree: &'a Arc<Tree>,
             hwnd: HWND,
             queue: Vec<QueuedEvent>,
-            text_changed: HashSet<NodeId>,
-        }
-        impl Handler<'_> {
-            fn insert_text_change_if_needed_parent(&mut self, node: Node) {
-                if !node.supports_text_ranges() {
-                    return;
-                }
-                let id = node.id();
-                if self.text_changed.contains(&id) {
-                    return;
-                }
-                let platform_node = PlatformNode::new(self.tree, node.id(), self.hwnd);
-                let element: IRawElementProviderSimple = platform_node.into();
-                // Text change events must come before selection change
-                // events. It doesn't matter if text change events come
-                // before other events.
-                self.queue.insert(
-                    0,
-                    QueuedEvent::Simple {
-                        element,
-                        event_id: UIA_Text_TextChangedEventId,
-                    },
-                );
-                self.text_changed.insert(id);
-            }
-            fn insert_text_change_if_needed(&mut self, node: &Node) {
-                if node.role() != Role::InlineTextBox {
-                    return;
-                }
-                if let Some(node) = node.filtered_parent(&filter) {
-                    self.insert_text_change_if_needed_parent(node);
-                }
-            }
-            fn insert_text_change_if_needed_for_removed_node(
-                &mut self,
-                node: &DetachedNode,
-                current_state: &TreeState,
-            ) {
-                if node.role() != Role::InlineTextBox {
-                    return;
-                }
-                if let Some(id) = node.parent_id() {
-                    if let Some(node) = current_state.node_by_id(id) {
-                        self.insert_text_change_if_needed_parent(node);
-                    }
-                }
-            }
         }
         impl TreeChangeHandler for Handler<'_> {
             fn node_added(&mut self, node: &Node) {
-                self.insert_text_change_if_needed(node);
                 if filter(node) != FilterResult::Include {
                     return;
                 }
@@ -112,9 +66,6 @@ impl Adapter {
                 }
             }
             fn node_updated(&mut self, old_node: &DetachedNode, new_node: &Node) {
-                if old_node.value() != new_node.value() {
-                    self.insert_text_change_if_needed(new_node);
-                }
                 if filter(new_node) != FilterResult::Include {
                     return;
                 }
@@ -145,8 +96,34 @@ impl Adapter {
                     });
                 }
             }
-            fn node_removed(&mut self, node: &DetachedNode, current_state: &TreeState) {
-                self.insert_text_change_if_needed_for_removed_node(node, current_state);
+            fn node_removed(&mut self, _node: &DetachedNode, _current_state: &TreeState) {}
+            fn text_changed(&mut self, document: &Node, _delta: &TextDelta) {
+                if filter(document) != FilterResult::Include {
+                    return;
+                }
+                let platform_node = PlatformNode::new(self.tree, document.id(), self.hwnd);
+                let element: IRawElementProviderSimple = platform_node.into();
+                // Text change events must come before selection change
+                // events. It doesn't matter if text change events come
+                // before other events.
+                self.queue.insert(
+                    0,
+                    QueuedEvent::Simple {
+                        element,
+                        event_id: UIA_Text_TextChangedEventId,
+                    },
+                );
+            }
+            fn selection_changed(&mut self, container: &Node) {
+                if filter(container) != FilterResult::Include {
+                    return;
+                }
+                let platform_node = PlatformNode::new(self.tree, container.id(), self.hwnd);
+                let element: IRawElementProviderSimple = platform_node.into();
+                self.queue.push(QueuedEvent::Simple {
+                    element,
+                    event_id: UIA_Selection_InvalidatedEventId,
+                });
             }
             // TODO: handle other events (#20)
         }
@@ -154,7 +131,6 @@ impl Adapter {
             tree: &self.tree,
             hwnd: self.hwnd,
             queue: Vec::new(),
-            text_changed: HashSet::new(),
         };
         self.tree.update_and_process_changes(update, &mut handler);
         QueuedEvents(handler.queue)
@@ -12,7 +12,9 @@
 
 use accesskit::kurbo::Point;
 use accesskit::{CheckedState, Live, NodeId, NodeIdContent, Role};
-use accesskit_consumer::{DetachedNode, FilterResult, Node, NodeState, Tree, TreeState};
+use accesskit_consumer::{
+    DetachedNode, FilterPolicy, FilterPolicyBuilder, FilterResult, Node, NodeState, Tree, TreeState,
+};
 use arrayvec::ArrayVec;
 use paste::paste;
 use std::sync::{Arc, Weak};
@@ -35,40 +37,20 @@ fn runtime_id_from_node_id(id: NodeId) -> impl std::ops::Deref<Target = [i32]> {
     result
 }
 
-fn filter_common(node: &NodeState) -> FilterResult {
-    if node.is_hidden() {
-        return FilterResult::ExcludeSubtree;
-    }
-
-    let role = node.role();
-    if role == Role::Presentation || role == Role::GenericContainer || role == Role::InlineTextBox {
-        return FilterResult::ExcludeNode;
-    }
-
-    FilterResult::Include
-}
+const FILTER_POLICY: FilterPolicy = FilterPolicyBuilder::new().build();
 
 pub(crate) fn filter(node: &Node) -> FilterResult {
-    if node.is_focused() {
-        return FilterResult::Include;
-    }
-
-    filter_common(node.state())
+    FILTER_POLICY.filter(node)
 }
 
 pub(crate) fn filter_detached(node: &DetachedNode) -> FilterResult {
-    if node.is_focused() {
-        return FilterResult::Include;
-    }
-
-    filter_common(node.state())
+    FILTER_POLICY.filter_detached(node)
 }
 
+const FILTER_POLICY_WITH_ROOT: FilterPolicy = FilterPolicyBuilder::new().include_root(true).build();
+
 fn filter_with_root_exception(node: &Node) -> FilterResult {
-    if node.is_root() {
-        return FilterResult::Include;
-    }
-    filter(node)
+    FILTER_POLICY_WITH_ROOT.filter(node)
 }
 
 pub(crate) enum NodeWrapper<'a> {
@@ -314,7 +296,11 @@ impl<'a> NodeWrapper<'a> {
     }
 
     fn is_enabled(&self) -> bool {
-        !self.node_state().is_disabled()
+        let is_disabled = match self {
+            Self::Node(node) => node.is_disabled(),
+            Self::DetachedNode(node) => node.is_disabled(),
+        };
+        !is_disabled
     }
 
     fn is_focusable(&self) -> bool {
@@ -369,7 +355,10 @@ impl<'a> NodeWrapper<'a> {
     }
 
     fn is_read_only(&self) -> bool {
-        self.node_state().is_read_only()
+        match self {
+            Self::Node(node) => node.is_read_only(),
+            Self::DetachedNode(node) => node.is_read_only(),
+        }
     }
 
     fn numeric_value(&self) -> f64 {
@@ -430,6 +419,20 @@ impl<'a> NodeWrapper<'a> {
         }
     }
 
+    fn is_selection_pattern_supported(&self) -> bool {
+        self.node_state().is_selection_container()
+    }
+
+    fn can_select_multiple(&self) -> bool {
+        self.node_state().is_multiselectable()
+    }
+
+    fn is_selection_required(&self) -> bool {
+        // We don't know whether the application would let the user
+        // deselect the last selected item, so don't promise anything.
+        false
+    }
+
     fn is_text_pattern_supported(&self) -> bool {
         match self {
             Self::Node(node) => node.supports_text_ranges(),
@@ -502,6 +505,7 @@ impl<'a> NodeWrapper<'a> {
     IValueProvider,
     IRangeValueProvider,
     ISelectionItemProvider,
+    ISelectionProvider,
     ITextProvider
 )]
 pub(crate) struct PlatformNode {
@@ -532,7 +536,7 @@ impl PlatformNode {
         F: FnOnce(&TreeState) -> Result<T>,
     {
         let tree = self.upgrade_tree()?;
-        let state = tree.read();
+        let state = tree.snapshot();
         f(&state)
     }
 
@@ -873,24 +877,63 @@ patterns! {
         (IsSelected, is_selected, BOOL)
     ), (
         fn Select(&self) -> Result<()> {
-            self.do_default_action()
+            let tree = self.validate_for_action()?;
+            // Radio buttons and the like are selected by activating them.
+            if self.resolve(|node| Ok(node.supports_select()))? {
+                tree.select(self.node_id);
+            } else {
+                tree.do_default_action(self.node_id);
+            }
+            Ok(())
         },
 
         fn AddToSelection(&self) -> Result<()> {
-            // TODO: implement when we work on list boxes (#23)
-            Err(not_implemented())
+            let tree = self.validate_for_action()?;
+            if !self.resolve(|node| Ok(node.supports_add_to_selection()))? {
+                return Err(not_implemented());
+            }
+            tree.add_to_selection(self.node_id);
+            Ok(())
         },
 
         fn RemoveFromSelection(&self) -> Result<()> {
-            // TODO: implement when we work on list boxes (#23)
-            Err(not_implemented())
+            let tree = self.validate_for_action()?;
+            if !self.resolve(|node| Ok(node.supports_remove_from_selection()))? {
+                return Err(not_implemented());
+            }
+            tree.remove_from_selection(self.node_id);
+            Ok(())
         },
 
         fn SelectionContainer(&self) -> Result<IRawElementProviderSimple> {
-            // TODO: implement when we work on list boxes (#23)
-            // We return E_FAIL here because that's what Chromium does
-            // if it can't find a container.
-            Err(Error::new(E_FAIL, "".into()))
+            self.resolve(|node| {
+                match node.selection_container() {
+                    Some(container) if filter(&container) == FilterResult::Include => {
+                        Ok(self.relative(container.id()).into())
+                    }
+                    // We return E_FAIL here because that's what Chromium does
+                    // if it can't find a container.
+                    _ => Err(Error::new(E_FAIL, "".into())),
+                }
+            })
+        }
+    )),
+    (Selection, is_selection_pattern_supported, (
+        (CanSelectMultiple, can_select_multiple, BOOL),
+        (IsSelectionRequired, is_selection_required, BOOL)
+    ), (
+        fn GetSelection(&self) -> Result<*mut SAFEARRAY> {
+            self.resolve(|node| {
+                let elements = node
+                    .selected_children()
+                    .filter(|item| filter(item) == FilterResult::Include)
+                    .map(|item| {
+                        let element: IRawElementProviderSimple = self.relative(item.id()).into();
+                        element.into()
+                    })
+                    .collect::<Vec<IUnknown>>();
+                Ok(safe_array_from_com_slice(&elements))
+            })
         }
     )),
     (Text, is_text_pattern_supported, (), (
@@ -913,9 +956,21 @@ patterns! {
             Ok(std::ptr::null_mut())
         },
 
-        fn RangeFromChild(&self, _child: &Option<IRawElementProviderSimple>) -> Result<ITextRangeProvider> {
-            // We don't support embedded objects in text.
-            Err(not_implemented())
+        fn RangeFromChild(&self, child: &Option<IRawElementProviderSimple>) -> Result<ITextRangeProvider> {
+            let child = required_param(child)?.as_impl();
+            if !self.tree.ptr_eq(&child.tree) {
+                return Err(invalid_arg());
+            }
+            self.resolve_for_text_pattern(|node| {
+                let range = node
+                    .tree_state
+                    .node_by_id(child.node_id)
+                    .and_then(|child| node.range_from_child(&child));
+                match range {
+                    Some(range) => Ok(PlatformTextRange::new(&self.tree, range, self.hwnd).into()),
+                    None => Err(invalid_arg()),
+                }
+            })
         },
 
         fn RangeFromPoint(&self, point: &UiaPoint) -> Result<ITextRangeProvider> {
@@ -334,3 +334,4 @@ impl IUIAutomationFocusChangedEventHandler_Impl for FocusEventHandler {
 
 mod simple;
 mod subclassed;
+mod text;
@@ -0,0 +1,84 @@
+// Copyright 2022 The AccessKit Authors. All rights reserved.
+// Licensed under the Apache License, Version 2.0 (found in
+// the LICENSE-APACHE file) or the MIT license (found in
+// the LICENSE-MIT file), at your option.
+
+use std::{convert::TryInto, num::NonZeroU128, sync::Arc};
+
+use accesskit::{Node, NodeId, Role, Tree, TreeUpdate};
+use windows::{core::*, Win32::UI::Accessibility::*};
+
+use super::{simple::NullActionHandler, *};
+use crate::util::VariantFactory;
+
+const WINDOW_TITLE: &str = "Text test";
+
+const WINDOW_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(1) });
+const LABEL_ID: NodeId = NodeId(unsafe { NonZeroU128::new_unchecked(2) });
+
+fn get_initial_state() -> TreeUpdate {
+    let root = Arc::new(Node {
+        role: Role::Window,
+        children: vec![LABEL_ID],
+        ..Default::default()
+    });
+    let label = Arc::new(Node {
+        role: Role::StaticText,
+        name: Some("Hello, world!".into()),
+        ..Default::default()
+    });
+    TreeUpdate {
+        nodes: vec![(WINDOW_ID, root), (LABEL_ID, label)],
+        tree: Some(Tree::new(WINDOW_ID)),
+        focus: None,
+    }
+}
+
+fn scope<F>(f: F) -> Result<()>
+where
+    F: FnOnce(&Scope) -> Result<()>,
+{
+    super::scope(
+        WINDOW_TITLE,
+        get_initial_state(),
+        WINDOW_ID,
+        Box::new(NullActionHandler {}),
+        f,
+    )
+}
+
+fn is_label(element: &IUIAutomationElement) -> bool {
+    let control_type = unsafe { element.CurrentControlType() }.unwrap();
+    let name = unsafe { element.CurrentName() }.unwrap();
+    let name: String = name.try_into().unwrap();
+    control_type == (UIA_TextControlTypeId.0 as i32) && name == "Hello, world!"
+}
+
+#[test]
+fn label_text_is_read_only() -> Result<()> {
+    scope(|s| {
+        let root = unsafe { s.uia.ElementFromHandle(s.window) }?;
+        let walker = unsafe { s.uia.ControlViewWalker() }?;
+
+        let mut label: Option<IUIAutomationElement> = None;
+        let mut wrapped_child = unsafe { walker.GetFirstChildElement(&root) };
+        while let Ok(child) = wrapped_child {
+            if is_label(&child) {
+                label = Some(child);
+                break;
+            }
+            wrapped_child = unsafe { walker.GetNextSiblingElement(&child) };
+        }
+        let label = label.unwrap();
+
+        let pattern: IUIAutomationTextPattern =
+            unsafe { label.GetCurrentPatternAs(UIA_TextPatternId.0 as i32) }?;
+        let range = unsafe { pattern.DocumentRange() }?;
+        let text: String = unsafe { range.GetText(-1) }?.try_into().unwrap();
+        assert_eq!(text, "Hello, world!");
+        let read_only = unsafe { range.GetAttributeValue(UIA_IsReadOnlyAttributeId.0 as i32) }?;
+        assert!(VariantFactory::from(true).matches(&read_only));
+
+        Ok(())
+    })
+}
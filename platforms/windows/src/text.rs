@@ -5,8 +5,10 @@
 
 #![allow(non_upper_case_globals)]
 
+use accesskit::{TextDecoration, VerticalOffset};
 use accesskit_consumer::{
-    Node, TextPosition as Position, TextRange as Range, Tree, TreeState, WeakTextRange as WeakRange,
+    FilterResult, LiveTextRange as LiveRange, Node, TextAttributeValue, TextFormat,
+    TextPosition as Position, TextRange as Range, TextUnit as Unit, Tree, TreeState,
 };
 use parking_lot::RwLock;
 use std::sync::{Arc, Weak};
@@ -15,31 +17,83 @@ use windows::{
     Win32::{Foundation::*, System::Com::*, UI::Accessibility::*},
 };
 
-use crate::{node::PlatformNode, util::*};
+use crate::{
+    node::{filter, PlatformNode},
+    util::*,
+};
+
+fn colorref_from_rgba(rgba: u32) -> i32 {
+    let [red, green, blue, _] = rgba.to_be_bytes();
+    i32::from_le_bytes([red, green, blue, 0])
+}
+
+fn text_decoration_style(decoration: Option<TextDecoration>) -> TextDecorationLineStyle {
+    match decoration {
+        None => TextDecorationLineStyle_None,
+        Some(TextDecoration::Solid) => TextDecorationLineStyle_Single,
+        Some(TextDecoration::Dotted) => TextDecorationLineStyle_Dot,
+        Some(TextDecoration::Dashed) => TextDecorationLineStyle_Dash,
+        Some(TextDecoration::Double) => TextDecorationLineStyle_Double,
+        Some(TextDecoration::Wavy) => TextDecorationLineStyle_Wavy,
+    }
+}
+
+/// Returns the value of a text attribute that comes from the formatting
+/// of the text, or `None` if the attribute isn't one of those.
+fn format_attribute_value(id: UIA_TEXTATTRIBUTE_ID, format: &TextFormat) -> Option<VariantFactory> {
+    Some(match id {
+        UIA_FontNameAttributeId => format.font_family.into(),
+        // UIA expects the size in points, and there are 72 points
+        // in 96 pixels.
+        UIA_FontSizeAttributeId => format.font_size.map(|size| f64::from(size) * 0.75).into(),
+        UIA_FontWeightAttributeId => {
+            let weight = match format.font_weight {
+                Some(weight) => Some(weight as i32),
+                None if format.bold => Some(700),
+                None => None,
+            };
+            weight.into()
+        }
+        UIA_IsItalicAttributeId => format.italic.into(),
+        UIA_BackgroundColorAttributeId => format.background_color.map(colorref_from_rgba).into(),
+        UIA_ForegroundColorAttributeId => format.foreground_color.map(colorref_from_rgba).into(),
+        UIA_OverlineStyleAttributeId => text_decoration_style(format.overline).into(),
+        UIA_StrikethroughStyleAttributeId => text_decoration_style(format.strikethrough).into(),
+        UIA_UnderlineStyleAttributeId => text_decoration_style(format.underline).into(),
+        UIA_IsSubscriptAttributeId => {
+            (format.vertical_offset == Some(VerticalOffset::Subscript)).into()
+        }
+        UIA_IsSuperscriptAttributeId => {
+            (format.vertical_offset == Some(VerticalOffset::Superscript)).into()
+        }
+        _ => return None,
+    })
+}
 
-fn upgrade_range<'a>(weak: &WeakRange, tree_state: &'a TreeState) -> Result<Range<'a>> {
-    if let Some(range) = weak.upgrade(tree_state) {
+fn upgrade_range<'a>(live: &LiveRange, tree_state: &'a TreeState) -> Result<Range<'a>> {
+    if let Some(range) = live.upgrade(tree_state) {
         Ok(range)
     } else {
         Err(element_not_available())
     }
 }
 
-fn upgrade_range_node<'a>(weak: &WeakRange, tree_state: &'a TreeState) -> Result<Node<'a>> {
-    if let Some(node) = weak.upgrade_node(tree_state) {
+fn upgrade_range_node<'a>(live: &LiveRange, tree_state: &'a TreeState) -> Result<Node<'a>> {
+    if let Some(node) = live.upgrade_node(tree_state) {
         Ok(node)
     } else {
         Err(element_not_available())
     }
 }
 
-fn weak_comparable_position_from_endpoint(
-    range: &WeakRange,
+fn live_offset_from_endpoint(
+    range: &LiveRange,
     endpoint: TextPatternRangeEndpoint,
-) -> Result<&(Vec<usize>, usize)> {
+) -> Result<usize> {
+    let (start, end) = range.offsets();
     match endpoint {
-        TextPatternRangeEndpoint_Start => Ok(range.start_comparable()),
-        TextPatternRangeEndpoint_End => Ok(range.end_comparable()),
+        TextPatternRangeEndpoint_Start => Ok(start),
+        TextPatternRangeEndpoint_End => Ok(end),
         _ => Err(invalid_arg()),
     }
 }
@@ -74,6 +128,19 @@ fn set_endpoint_position<'a>(
     Ok(())
 }
 
+fn unit_from_text_unit(unit: TextUnit) -> Result<Unit> {
+    match unit {
+        TextUnit_Character => Ok(Unit::Character),
+        TextUnit_Format => Ok(Unit::Format),
+        TextUnit_Word => Ok(Unit::Word),
+        TextUnit_Line => Ok(Unit::Line),
+        TextUnit_Paragraph => Ok(Unit::Paragraph),
+        TextUnit_Page => Ok(Unit::Page),
+        TextUnit_Document => Ok(Unit::Document),
+        _ => Err(invalid_arg()),
+    }
+}
+
 fn back_to_unit_start(start: Position, unit: TextUnit) -> Result<Position> {
     match unit {
         TextUnit_Character => {
@@ -205,15 +272,17 @@ fn move_position(
 #[implement(ITextRangeProvider)]
 pub(crate) struct PlatformRange {
     tree: Weak<Tree>,
-    state: RwLock<WeakRange>,
+    state: RwLock<LiveRange>,
     hwnd: HWND,
 }
 
 impl PlatformRange {
     pub(crate) fn new(tree: &Weak<Tree>, range: Range, hwnd: HWND) -> Self {
+        // The range was just obtained from the tree, so it's still alive.
+        let state = tree.upgrade().unwrap().track_text_range(&range);
         Self {
             tree: tree.clone(),
-            state: RwLock::new(range.downgrade()),
+            state: RwLock::new(state),
             hwnd,
         }
     }
@@ -270,10 +339,10 @@ impl PlatformRange {
         F: FnOnce(&mut Range) -> Result<T>,
     {
         self.with_tree_state(|tree_state| {
-            let mut state = self.state.write();
+            let state = self.state.write();
             let mut range = upgrade_range(&state, tree_state)?;
             let result = f(&mut range);
-            *state = range.downgrade();
+            state.set(&range);
             result
         })
     }
@@ -335,10 +404,9 @@ impl ITextRangeProvider_Impl for PlatformRange {
             // to determine whether an old range is degenerate even if
             // that range is no longer valid.
             let state = self.state.read();
-            let other_state = other.state.read();
-            let pos = weak_comparable_position_from_endpoint(&state, endpoint)?;
-            let other_pos = weak_comparable_position_from_endpoint(&other_state, other_endpoint)?;
-            let result = pos.cmp(other_pos);
+            let pos = live_offset_from_endpoint(&state, endpoint)?;
+            let other_pos = live_offset_from_endpoint(&state, other_endpoint)?;
+            let result = pos.cmp(&other_pos);
             return Ok(result as i32);
         }
         self.require_same_tree(other)?;
@@ -361,52 +429,64 @@ impl ITextRangeProvider_Impl for PlatformRange {
             // range even if the current endpoints are now invalid.
             // Based on observed behavior, Narrator needs this ability.
             return self.with_tree_state(|tree_state| {
-                let mut state = self.state.write();
+                let state = self.state.write();
                 let node = upgrade_range_node(&state, tree_state)?;
-                *state = node.document_range().downgrade();
+                state.set(&node.document_range());
                 Ok(())
             });
         }
+        let unit = unit_from_text_unit(unit)?;
         self.write(|range| {
-            let start = range.start();
-            if unit == TextUnit_Character && start.is_document_end() {
-                // We know from experimentation that some Windows ATs
-                // expect ExpandToEnclosingUnit(TextUnit_Character)
-                // to do nothing if the range is degenerate at the end
-                // of the document.
-                return Ok(());
-            }
-            let start = back_to_unit_start(start, unit)?;
-            range.set_start(start);
-            if !start.is_document_end() {
-                let end = move_forward_to_end(start, unit)?;
-                range.set_end(end);
-            }
+            // We know from experimentation that some Windows ATs
+            // expect ExpandToEnclosingUnit(TextUnit_Character)
+            // to do nothing if the range is degenerate at the end
+            // of the document, which is what the enclosing unit is
+            // in that case.
+            *range = range.start().enclosing_unit(unit);
             Ok(())
         })
     }
 
     fn FindAttribute(
         &self,
-        _id: i32,
-        _value: &VARIANT,
-        _backward: BOOL,
+        id: i32,
+        value: &VARIANT,
+        backward: BOOL,
     ) -> Result<ITextRangeProvider> {
-        // TODO: implement when we support variable formatting (part of rich text)
-        // Justification: JUCE doesn't implement this.
-        Err(Error::OK)
+        let id = UIA_TEXTATTRIBUTE_ID(id as _);
+        self.read(|range| {
+            let found = range.find_attribute(
+                |format| {
+                    matches!(
+                        format_attribute_value(id, format),
+                        Some(format_value) if format_value.matches(value)
+                    )
+                },
+                &true,
+                backward.into(),
+            );
+            if let Some(found) = found {
+                Ok(PlatformRange::new(&self.tree, found, self.hwnd).into())
+            } else {
+                Err(Error::OK)
+            }
+        })
     }
 
     fn FindText(
         &self,
-        _text: &BSTR,
-        _backward: BOOL,
-        _ignore_case: BOOL,
+        text: &BSTR,
+        backward: BOOL,
+        ignore_case: BOOL,
     ) -> Result<ITextRangeProvider> {
-        // TODO: implement when there's a real-world use case that requires it
-        // Justification: Quorum doesn't implement this and is being used
-        // by blind students.
-        Err(Error::OK)
+        self.read(|range| {
+            let needle = text.to_string();
+            if let Some(found) = range.find_text(&needle, backward.into(), ignore_case.into()) {
+                Ok(PlatformRange::new(&self.tree, found, self.hwnd).into())
+            } else {
+                Err(Error::OK)
+            }
+        })
     }
 
     fn GetAttributeValue(&self, id: i32) -> Result<VARIANT> {
@@ -431,11 +511,22 @@ impl ITextRangeProvider_Impl for PlatformRange {
                 }
                 Ok(VariantFactory::from(value).into())
             }),
-            // TODO: implement more attributes
-            _ => {
-                let value = unsafe { UiaGetReservedNotSupportedValue() }.unwrap();
-                Ok(VariantFactory::from(value).into())
-            }
+            _ => self.read(|range| {
+                match range.attribute(|node| format_attribute_value(id, &node.text_format())) {
+                    TextAttributeValue::Single(Some(value)) if !value.is_empty() => {
+                        Ok(value.into())
+                    }
+                    TextAttributeValue::Mixed => {
+                        let value = unsafe { UiaGetReservedMixedAttributeValue() }.unwrap();
+                        Ok(VariantFactory::from(value).into())
+                    }
+                    // TODO: implement more attributes
+                    _ => {
+                        let value = unsafe { UiaGetReservedNotSupportedValue() }.unwrap();
+                        Ok(VariantFactory::from(value).into())
+                    }
+                }
+            }),
         }
     }
 
@@ -459,8 +550,14 @@ impl ITextRangeProvider_Impl for PlatformRange {
     }
 
     fn GetEnclosingElement(&self) -> Result<IRawElementProviderSimple> {
-        self.with_node(|node| {
-            // Revisit this if we eventually support embedded objects.
+        self.read(|range| {
+            // Inline text boxes aren't elements, so the enclosing element
+            // is the nearest included ancestor of the node that encloses
+            // the range, or the range's node itself.
+            let mut node = range.enclosing_node();
+            while node.id() != range.node().id() && filter(&node) != FilterResult::Include {
+                node = node.parent().unwrap();
+            }
             Ok(PlatformNode {
                 tree: self.tree.clone(),
                 node_id: node.id(),
@@ -527,14 +624,14 @@ impl ITextRangeProvider_Impl for PlatformRange {
         // to avoid deadlock.
         self.with_tree_state(|tree_state| {
             let other_range = other.upgrade_for_read(tree_state)?;
-            let mut state = self.state.write();
+            let state = self.state.write();
             let mut range = upgrade_range(&state, tree_state)?;
             if range.node().id() != other_range.node().id() {
                 return Err(invalid_arg());
             }
             let pos = position_from_endpoint(&other_range, other_endpoint)?;
             set_endpoint_position(&mut range, endpoint, pos)?;
-            *state = range.downgrade();
+            state.set(&range);
             Ok(())
         })
     }
@@ -569,7 +666,22 @@ impl ITextRangeProvider_Impl for PlatformRange {
     }
 
     fn GetChildren(&self) -> Result<*mut SAFEARRAY> {
-        // We don't support embedded objects in text.
-        Ok(safe_array_from_com_slice(&[]))
+        self.read(|range| {
+            let children = range
+                .embedded_objects()
+                .into_iter()
+                .filter(|node| filter(node) == FilterResult::Include)
+                .map(|node| {
+                    let element: IRawElementProviderSimple = PlatformNode {
+                        tree: self.tree.clone(),
+                        node_id: node.id(),
+                        hwnd: self.hwnd,
+                    }
+                    .into();
+                    element.into()
+                })
+                .collect::<Vec<IUnknown>>();
+            Ok(safe_array_from_com_slice(&children))
+        })
     }
 }
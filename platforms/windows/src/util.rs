@@ -44,6 +44,35 @@ impl VariantFactory {
     pub(crate) fn is_empty(&self) -> bool {
         self.0 == VT_EMPTY
     }
+
+    /// Returns true if `variant` holds the same value. Only the variant
+    /// types that this factory produces can match.
+    pub(crate) fn matches(&self, variant: &VARIANT) -> bool {
+        // SAFETY: Every variant has a type tag.
+        let variant = unsafe { &variant.Anonymous.Anonymous };
+        variant.vt == self.0 && variant_values_eq(self.0, &self.1, &variant.Anonymous)
+    }
+}
+
+impl PartialEq for VariantFactory {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 && variant_values_eq(self.0, &self.1, &other.1)
+    }
+}
+
+fn variant_values_eq(vt: VARENUM, a: &VARIANT_0_0_0, b: &VARIANT_0_0_0) -> bool {
+    // SAFETY: Both values are of type `vt`, so we only read the field
+    // that's valid for that type.
+    unsafe {
+        match vt {
+            VT_EMPTY => true,
+            VT_BOOL => (a.boolVal == VARIANT_FALSE) == (b.boolVal == VARIANT_FALSE),
+            VT_I4 => a.lVal == b.lVal,
+            VT_R8 => a.dblVal == b.dblVal,
+            VT_BSTR => *a.bstrVal == *b.bstrVal,
+            _ => false,
+        }
+    }
 }
 
 impl From<&str> for VariantFactory {
@@ -112,6 +141,12 @@ impl From<CaretPosition> for VariantFactory {
     }
 }
 
+impl From<TextDecorationLineStyle> for VariantFactory {
+    fn from(value: TextDecorationLineStyle) -> Self {
+        value.0.into()
+    }
+}
+
 impl From<UIA_CONTROLTYPE_ID> for VariantFactory {
     fn from(value: UIA_CONTROLTYPE_ID) -> Self {
         (value.0 as i32).into()